@@ -0,0 +1,151 @@
+//! Read throughput of already filled caches under contention, comparing
+//! the per-core reader counts behind `AtomicCache` with a value kept in a
+//! `RwLock<Option<T>>`, and the single lock of `AtomicCacheMap` with the
+//! shards of `ShardedCacheMap`, both when every key is stored already and
+//! when every read misses.
+//!
+//! Run with `cargo bench`.
+
+use cache::{AtomicCache, AtomicCacheMap, ShardedCacheMap};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Barrier, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const READS_PER_THREAD: usize = 1_000_000;
+
+/// the number of distinct keys read from the keyed caches
+const KEYS: usize = 1024;
+
+/// the layout `AtomicCache` used to have, where every read takes the read
+/// lock around an optional value
+struct RwLockCache<T, F> {
+    calc: F,
+    data: RwLock<Option<T>>,
+}
+
+impl<T, F: Fn() -> T> RwLockCache<T, F> {
+    fn new(calc: F) -> Self {
+        RwLockCache {
+            calc,
+            data: RwLock::new(None),
+        }
+    }
+
+    fn get(&self) -> RwLockReadGuard<'_, Option<T>> {
+        loop {
+            let read = self.data.read().unwrap();
+            if read.is_some() {
+                return read;
+            }
+            drop(read);
+
+            let mut write = self.data.write().unwrap();
+            if write.is_none() {
+                *write = Some((self.calc)());
+            }
+        }
+    }
+}
+
+/// runs `read` `READS_PER_THREAD` times on each of `threads` threads at once,
+/// passing it the number of the read, and returns the time taken by the
+/// slowest of them
+fn contend(threads: usize, read: impl Fn(usize) -> usize + Sync) -> Duration {
+    let barrier = Barrier::new(threads);
+
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    let start = Instant::now();
+
+                    for i in 0..READS_PER_THREAD {
+                        black_box(read(i));
+                    }
+
+                    start.elapsed()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .max()
+            .unwrap()
+    })
+}
+
+fn report(name: &str, threads: usize, elapsed: Duration) {
+    let reads = (threads * READS_PER_THREAD) as f64;
+    let per_sec = reads / elapsed.as_secs_f64();
+
+    println!(
+        "{:<20} {:>3} threads {:>10.1?} {:>8.1} M reads/s",
+        name,
+        threads,
+        elapsed,
+        per_sec / 1e6
+    );
+}
+
+fn main() {
+    for &threads in &[1, 2, 4, 8, 16] {
+        let rwlock = RwLockCache::new(|| 55);
+        drop(rwlock.get());
+        report(
+            "RwLock<Option<T>>",
+            threads,
+            contend(threads, |_| rwlock.get().unwrap()),
+        );
+
+        let atomic = AtomicCache::new(|| 55);
+        atomic.get();
+        report("AtomicCache", threads, contend(threads, |_| *atomic.get()));
+    }
+
+    for &threads in &[1, 2, 4, 8, 16] {
+        let locked = AtomicCacheMap::new(|n: &usize| n * n);
+        let sharded = ShardedCacheMap::new(|n: &usize| n * n);
+
+        for n in 0..KEYS {
+            locked.get(&n);
+            sharded.get(&n);
+        }
+
+        let read = |i| i * 7919 % KEYS;
+        report(
+            "AtomicCacheMap",
+            threads,
+            contend(threads, |i| *locked.get(&read(i))),
+        );
+        report(
+            "ShardedCacheMap",
+            threads,
+            contend(threads, |i| *sharded.get(&read(i))),
+        );
+    }
+
+    for &threads in &[1, 2, 4, 8, 16] {
+        let locked = AtomicCacheMap::with_capacity(|n: &usize| n * n, KEYS);
+        let sharded = ShardedCacheMap::with_capacity(|n: &usize| n * n, KEYS);
+
+        // every read asks for a key that has never been seen, so each one
+        // computes and stores a value and evicts another
+        let next = AtomicUsize::new(0);
+        let miss = || next.fetch_add(1, Ordering::Relaxed);
+        report(
+            "AtomicCacheMap miss",
+            threads,
+            contend(threads, |_| *locked.get(&miss())),
+        );
+        report(
+            "ShardedCacheMap miss",
+            threads,
+            contend(threads, |_| *sharded.get(&miss())),
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! The TinyLFU admission filter, which keeps rarely used keys from pushing
+//! popular ones out of a full keyed cache.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// the number of counters each key is spread over
+const DEPTH: usize = 4;
+
+/// counters saturate at this value, as in the 4-bit counters of the
+/// original paper
+const MAX_COUNT: u8 = 15;
+
+/// the number of counters per row for a cache holding `capacity` values
+fn width(capacity: Option<usize>) -> usize {
+    capacity.unwrap_or(0).max(64).next_power_of_two()
+}
+
+/// estimates how often each key has been asked for recently, and admits a
+/// new key into a full cache only if it is asked for more often than the
+/// key that would be evicted to make room for it
+///
+/// The frequencies are kept in a count-min sketch, so the memory used only
+/// depends on the capacity of the cache, not on how many distinct keys have
+/// been seen. Every so often all counters are halved, so that keys that
+/// used to be popular fade away.
+pub(crate) struct TinyLfu {
+    /// `DEPTH` rows of `width` counters each
+    counters: Vec<u8>,
+    width: usize,
+    /// the number of increments since the counters were last halved
+    additions: usize,
+    /// the number of increments after which the counters are halved
+    sample_size: usize,
+    hasher: RandomState,
+}
+
+impl TinyLfu {
+    pub(crate) fn new(capacity: Option<usize>) -> Self {
+        let width = width(capacity);
+
+        TinyLfu {
+            counters: vec![0; DEPTH * width],
+            width,
+            additions: 0,
+            sample_size: 10 * width,
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// resizes the sketch to suit `capacity`, carrying the estimates over
+    ///
+    /// Widths are powers of two and a key's column is its hash masked by the
+    /// width, so every old column lands in a known set of new ones. Growing
+    /// copies each counter into all columns it could now be in, and
+    /// shrinking adds up the counters that now share a column, so no key is
+    /// estimated lower than before.
+    pub(crate) fn resize(&mut self, capacity: Option<usize>) {
+        let width = width(capacity);
+        if width == self.width {
+            return;
+        }
+
+        let mut counters = vec![0; DEPTH * width];
+        let old_rows = self.counters.chunks(self.width);
+        let new_rows = counters.chunks_mut(width);
+
+        for (old, new) in old_rows.zip(new_rows) {
+            if width > self.width {
+                for (column, counter) in new.iter_mut().enumerate() {
+                    *counter = old[column % self.width];
+                }
+            } else {
+                for (column, &count) in old.iter().enumerate() {
+                    let counter = &mut new[column % width];
+                    *counter = (*counter + count).min(MAX_COUNT);
+                }
+            }
+        }
+
+        self.counters = counters;
+        self.width = width;
+        self.sample_size = 10 * width;
+
+        if self.additions >= self.sample_size {
+            self.age();
+        }
+    }
+
+    /// counts one request for `key`
+    pub(crate) fn record<K: Hash>(&mut self, key: &K) {
+        let mut added = false;
+
+        for i in self.indices(key) {
+            if self.counters[i] < MAX_COUNT {
+                self.counters[i] += 1;
+                added = true;
+            }
+        }
+
+        if added {
+            self.additions += 1;
+
+            if self.additions >= self.sample_size {
+                self.age();
+            }
+        }
+    }
+
+    /// whether `candidate` should take the place of `victim`
+    pub(crate) fn admit<K: Hash>(&self, candidate: &K, victim: &K) -> bool {
+        self.estimate(candidate) > self.estimate(victim)
+    }
+
+    fn estimate<K: Hash>(&self, key: &K) -> u8 {
+        self.indices(key).map(|i| self.counters[i]).min().unwrap()
+    }
+
+    /// halves every counter
+    fn age(&mut self) {
+        for counter in &mut self.counters {
+            *counter /= 2;
+        }
+
+        self.additions /= 2;
+    }
+
+    /// the counter for `key` in each row, derived from a single hash by
+    /// double hashing
+    fn indices<K: Hash>(&self, key: &K) -> impl Iterator<Item = usize> {
+        let hash = self.hasher.hash_one(key);
+        let (low, high) = (hash as usize, (hash >> 32) as usize | 1);
+        let mask = self.width - 1;
+        let width = self.width;
+
+        (0..DEPTH).map(move |row| row * width + (low.wrapping_add(row.wrapping_mul(high)) & mask))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequent_keys_are_admitted() {
+        let mut filter = TinyLfu::new(Some(100));
+
+        for _ in 0..5 {
+            filter.record(&"hot");
+        }
+        filter.record(&"cold");
+
+        assert!(filter.admit(&"hot", &"cold"));
+        assert!(!filter.admit(&"cold", &"hot"));
+        assert!(!filter.admit(&"cold", &"cold"));
+    }
+
+    #[test]
+    fn test_estimates_survive_resizing() {
+        let mut filter = TinyLfu::new(Some(100));
+
+        for _ in 0..5 {
+            filter.record(&"hot");
+        }
+
+        for capacity in [Some(1000), Some(10), None] {
+            filter.resize(capacity);
+            filter.record(&"newcomer");
+
+            assert!(!filter.admit(&"newcomer", &"hot"));
+            assert!(filter.admit(&"hot", &"cold"));
+        }
+    }
+
+    #[test]
+    fn test_counters_age() {
+        let mut filter = TinyLfu::new(Some(16));
+
+        for _ in 0..8 {
+            filter.record(&"old");
+        }
+
+        filter.age();
+        assert_eq!(filter.estimate(&"old"), 4);
+    }
+}
@@ -0,0 +1,295 @@
+//! A lazily evaluated cache whose value is produced by a future.
+
+use crate::stats::{AtomicStats, CacheStats};
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Instant;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// an asynchronous variant of [`AtomicCache`]. The closure returns a future,
+/// and every task awaiting [`get`] while the cache is empty shares a single
+/// in-flight computation instead of blocking its executor thread.
+///
+/// The in-flight future is driven by whichever awaiting task happens to poll
+/// it, and every awaiting task is woken whenever it makes progress. Dropping
+/// an awaiting task does not cancel the computation; the next call to
+/// [`get`] picks it back up where it left off. If the future panics, the
+/// computation is dropped and the next task to poll starts a new one.
+///
+/// [`AtomicCache`]: ./struct.AtomicCache.html
+/// [`get`]: #method.get
+pub struct AsyncCache<T> {
+    calc: Box<dyn Fn() -> BoxFuture<T> + Send + Sync>,
+    data: OnceLock<T>,
+    pending: Mutex<Option<Pending<T>>>,
+    stats: AtomicStats,
+}
+
+/// a computation that has been started but has not finished yet
+struct Pending<T> {
+    future: BoxFuture<T>,
+    wakers: Arc<Wakers>,
+    started: Instant,
+}
+
+/// the wakers of every task waiting on a [`Pending`] computation. The
+/// computation is polled with a waker that wakes all of them, so it does not
+/// matter which task ends up driving it.
+#[derive(Default)]
+struct Wakers(Mutex<Vec<Waker>>);
+
+impl Wakers {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = lock(&self.0);
+
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl Wake for Wakers {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers: Vec<_> = lock(&self.0).drain(..).collect();
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> AsyncCache<T> {
+    /// Constructs a new AsyncCache using a closure that returns a future
+    /// which lazily evaluates to the value that will be cached.
+    /// ```
+    /// # use cache::AsyncCache;
+    /// # use futures::executor::block_on;
+    /// let cache = AsyncCache::new(|| async { 55 });
+    ///
+    /// assert_eq!(*block_on(cache.get()), 55);
+    /// ```
+    pub fn new<F, Fut>(calc: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        AsyncCache {
+            calc: Box::new(move || Box::pin(calc())),
+            data: OnceLock::new(),
+            pending: Mutex::new(None),
+            stats: AtomicStats::default(),
+        }
+    }
+
+    /// gets a reference to the cached value, computing it first if it
+    /// does not exist
+    ///
+    /// The closure is called at most once. Tasks that call `get` while the
+    /// value is being computed wait for that computation to finish.
+    ///
+    /// The future returned by the closure must not await `get` on the same
+    /// cache, as that would deadlock.
+    /// ```
+    /// # use cache::AsyncCache;
+    /// # use futures::executor::block_on;
+    /// # use futures::future::join;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    /// let counter = Arc::clone(&calls);
+    /// let cache = AsyncCache::new(move || {
+    ///     counter.fetch_add(1, Ordering::SeqCst);
+    ///     async { 55 }
+    /// });
+    ///
+    /// let (a, b) = block_on(join(cache.get(), cache.get()));
+    /// assert_eq!((*a, *b), (55, 55));
+    /// assert_eq!(calls.load(Ordering::SeqCst), 1);
+    /// ```
+    pub async fn get(&self) -> &T {
+        if let Some(value) = self.data.get() {
+            self.stats.hit();
+            return value;
+        }
+
+        self.stats.miss();
+        future::poll_fn(|cx| self.poll_get(cx)).await
+    }
+
+    /// starts recording how often the cache is hit and how long the future
+    /// takes, which [`stats`] then reports. The load time of the future runs
+    /// from when it is created until it completes, including any time it
+    /// spends waiting to be polled.
+    /// ```
+    /// # use cache::AsyncCache;
+    /// # use futures::executor::block_on;
+    /// let cache = AsyncCache::new(|| async { 55 }).record_stats();
+    ///
+    /// block_on(cache.get());
+    /// block_on(cache.get());
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.stats.enable();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::AsyncCache;
+    /// let cache = AsyncCache::new(|| async { 55 });
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.stats.snapshot()
+    }
+
+    fn poll_get(&self, cx: &mut Context<'_>) -> Poll<&T> {
+        if let Some(value) = self.data.get() {
+            return Poll::Ready(value);
+        }
+
+        let mut pending = Polling(lock(&self.pending));
+
+        // the computation may have finished while we were waiting for the
+        // lock
+        if let Some(value) = self.data.get() {
+            return Poll::Ready(value);
+        }
+
+        let calc = &self.calc;
+        let current = pending.0.get_or_insert_with(|| Pending {
+            future: calc(),
+            wakers: Arc::new(Wakers::default()),
+            started: Instant::now(),
+        });
+        current.wakers.register(cx.waker());
+
+        let waker = Waker::from(Arc::clone(&current.wakers));
+        let value = match current
+            .future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+        {
+            Poll::Ready(value) => value,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        self.stats.load(current.started.elapsed());
+        let wakers = Arc::clone(&current.wakers);
+        *pending.0 = None;
+        let value = self.data.get_or_init(|| value);
+        drop(pending);
+
+        // let every other task waiting on the computation see the value
+        wakers.wake();
+
+        Poll::Ready(value)
+    }
+}
+
+/// the lock on the pending computation while it is being polled. If the
+/// future panics, the computation is dropped and the tasks waiting on it are
+/// woken, so that the next one to poll starts over instead of polling a
+/// future that has already panicked.
+struct Polling<'a, T>(MutexGuard<'a, Option<Pending<T>>>);
+
+impl<'a, T> Drop for Polling<'a, T> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Some(pending) = self.0.take() {
+                pending.wakers.wake_by_ref();
+            }
+        }
+    }
+}
+
+/// takes a lock, ignoring poisoning. A future that panics poisons the lock
+/// on the pending computation, but [`Polling`] has already dropped the
+/// computation by then, so there is nothing left to clean up.
+///
+/// [`Polling`]: ./struct.Polling.html
+fn lock<T>(data: &Mutex<T>) -> MutexGuard<'_, T> {
+    data.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_awaiters_share_one_computation() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = oneshot::channel::<usize>();
+        let receiver = Mutex::new(Some(receiver));
+
+        let counter = Arc::clone(&calls);
+        let cache = Arc::new(AsyncCache::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let receiver = receiver.lock().unwrap().take().unwrap();
+
+            async move { receiver.await.unwrap() }
+        }));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || *block_on(cache.get()))
+            })
+            .collect();
+
+        thread::sleep(std::time::Duration::from_millis(50));
+        sender.send(55).unwrap();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 55);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_panicking_future_is_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let cache = Arc::new(AsyncCache::new(move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+
+            async move {
+                if call == 0 {
+                    panic!("first attempt fails");
+                }
+
+                55
+            }
+        }));
+
+        let first = Arc::clone(&cache);
+        assert!(thread::spawn(move || *block_on(first.get()))
+            .join()
+            .is_err());
+
+        assert_eq!(*block_on(cache.get()), 55);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
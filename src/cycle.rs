@@ -0,0 +1,301 @@
+//! Detection of caches whose closures end up depending on themselves.
+
+use std::any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError};
+
+thread_local! {
+    /// the caches whose closures are currently running on this thread,
+    /// outermost first
+    static INITIALIZING: RefCell<Vec<(usize, Label)>> = const { RefCell::new(Vec::new()) };
+
+    /// a number identifying this thread, which unlike a `ThreadId` fits in
+    /// an atomic. Never zero.
+    static THREAD: usize = {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    };
+}
+
+fn current_thread() -> usize {
+    THREAD.with(|thread| *thread)
+}
+
+/// the thread running the closure of a thread-safe cache, if any. Kept in
+/// the cache itself, so that starting a computation takes no lock shared
+/// with other caches.
+#[derive(Default)]
+pub(crate) struct Owner(AtomicUsize);
+
+impl Owner {
+    fn get(&self) -> Option<usize> {
+        match self.0.load(Ordering::SeqCst) {
+            0 => None,
+            thread => Some(thread),
+        }
+    }
+}
+
+/// what a cache is called in a [`CycleError`]: the name it was given, or
+/// its type and address otherwise. Making one never allocates; the
+/// description is only formatted once a cycle has been found.
+///
+/// [`CycleError`]: ./struct.CycleError.html
+#[derive(Clone)]
+pub(crate) struct Label {
+    name: Option<Arc<str>>,
+    kind: &'static str,
+    address: usize,
+}
+
+impl Label {
+    /// the label of a cache of `T`s that lives at `address`
+    pub(crate) fn new<T>(name: &Option<Arc<str>>, address: usize) -> Self {
+        Label {
+            name: name.clone(),
+            kind: any::type_name::<T>(),
+            address,
+        }
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match self.name {
+            Some(ref name) => name.to_string(),
+            None => format!("<{} cache at {:#x}>", self.kind, self.address),
+        }
+    }
+}
+
+/// which cache each blocked thread is waiting on. Following these edges,
+/// and the owners of the caches, from a cache back to the current thread
+/// means that blocking would deadlock. Only threads that are about to block
+/// ever lock the graph.
+#[derive(Default)]
+pub(crate) struct Graph {
+    waiting: HashMap<usize, Wait>,
+}
+
+/// the cache a blocked thread is waiting on
+struct Wait {
+    id: usize,
+    label: Label,
+    owner: *const Owner,
+}
+
+// SAFETY: the owners are only looked at while the graph is locked, and a
+// thread removes its entry, under that same lock, before it stops borrowing
+// the cache it was waiting on
+unsafe impl Send for Graph {}
+
+static GRAPH: LazyLock<Mutex<Graph>> = LazyLock::new(Default::default);
+
+pub(crate) fn graph() -> MutexGuard<'static, Graph> {
+    // the graph is only ever updated in small steps that cannot panic, so
+    // it stays consistent even if some unrelated thread panicked while
+    // holding the lock
+    GRAPH.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// the names of the caches on the current thread's stack, starting with the
+/// cache identified by `id`
+fn stack_from(id: usize) -> Vec<String> {
+    INITIALIZING.with(|stack| {
+        let stack = stack.borrow();
+        let start = stack.iter().position(|&(other, _)| other == id);
+
+        match start {
+            Some(start) => stack[start..]
+                .iter()
+                .map(|(_, label)| label.describe())
+                .collect(),
+            None => Vec::new(),
+        }
+    })
+}
+
+/// an error returned when the closure of a cache asks that same cache for its
+/// value, either directly or through the closures of other caches. For
+/// thread-safe caches this includes closures running on different threads
+/// that would otherwise wait on each other forever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleError {
+    caches: Vec<String>,
+}
+
+impl CycleError {
+    /// the names of the caches involved in the cycle, in the order in which
+    /// they were entered. The first and last names are the same cache.
+    /// ```
+    /// # use cache::Cache;
+    /// # use std::cell::OnceCell;
+    /// # use std::rc::Rc;
+    /// let slot = Rc::new(OnceCell::<Cache<usize>>::new());
+    /// let weak = Rc::downgrade(&slot);
+    /// let cache = slot.get_or_init(|| {
+    ///     let calc: Box<dyn Fn() -> usize> = Box::new(move || {
+    ///         let slot = weak.upgrade().unwrap();
+    ///         let err = slot.get().unwrap().try_get().err().unwrap();
+    ///
+    ///         err.caches().len()
+    ///     });
+    ///
+    ///     Cache::new(calc).named("config")
+    /// });
+    ///
+    /// assert_eq!(*cache.get(), 2);
+    /// ```
+    pub fn caches(&self) -> &[String] {
+        &self.caches
+    }
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cycle detected while initializing caches: ")?;
+
+        for (i, cache) in self.caches.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+
+            write!(f, "{}", cache)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for CycleError {}
+
+/// marks a cache as being initialized by the current thread until it is
+/// dropped
+pub(crate) struct Initializing<'a> {
+    id: usize,
+    owner: Option<&'a Owner>,
+}
+
+impl Initializing<'static> {
+    /// records that the current thread is about to run the closure of the
+    /// cache identified by `id`, failing if it is already doing so further
+    /// up the stack
+    pub(crate) fn enter(id: usize, label: Label) -> Result<Self, CycleError> {
+        push(id, label)?;
+
+        Ok(Initializing { id, owner: None })
+    }
+}
+
+impl<'a> Initializing<'a> {
+    /// like [`enter`], but also records the current thread as the owner of
+    /// the cache so that other threads can tell when waiting on it would
+    /// deadlock
+    ///
+    /// [`enter`]: #method.enter
+    pub(crate) fn enter_shared(
+        id: usize,
+        label: Label,
+        owner: &'a Owner,
+    ) -> Result<Self, CycleError> {
+        push(id, label)?;
+        owner.0.store(current_thread(), Ordering::SeqCst);
+
+        Ok(Initializing {
+            id,
+            owner: Some(owner),
+        })
+    }
+}
+
+/// pushes the cache identified by `id` onto the current thread's stack,
+/// failing if it is already on it
+fn push(id: usize, label: Label) -> Result<(), CycleError> {
+    INITIALIZING.with(|stack| {
+        let mut stack = stack.borrow_mut();
+
+        if stack.iter().any(|&(other, _)| other == id) {
+            drop(stack);
+
+            let mut caches = stack_from(id);
+            caches.push(label.describe());
+
+            return Err(CycleError { caches });
+        }
+
+        stack.push((id, label));
+        Ok(())
+    })
+}
+
+impl<'a> Drop for Initializing<'a> {
+    fn drop(&mut self) {
+        if let Some(owner) = self.owner {
+            owner.0.store(0, Ordering::SeqCst);
+        }
+
+        INITIALIZING.with(|stack| {
+            let mut stack = stack.borrow_mut();
+
+            if let Some(pos) = stack.iter().rposition(|&(other, _)| other == self.id) {
+                stack.remove(pos);
+            }
+        });
+    }
+}
+
+/// marks the current thread as blocked on a thread-safe cache until it is
+/// dropped
+pub(crate) struct Waiting;
+
+impl Waiting {
+    /// records that the current thread is about to block on the cache
+    /// identified by `id`, whose closure is run by `owner`, failing if that
+    /// thread is, directly or through other threads, waiting on a cache
+    /// that the current thread is initializing
+    pub(crate) fn enter(id: usize, label: Label, owner: &Owner) -> Result<Self, CycleError> {
+        let me = current_thread();
+        let mut graph = graph();
+
+        // the caches that the owners of `id` and its successors are waiting
+        // on, which only grows when the owners are themselves blocked. An
+        // owner that has just moved on can make the chain loop without
+        // passing through this thread, so it is never followed for longer
+        // than there are threads waiting.
+        let mut chain: Vec<&Label> = Vec::new();
+        let mut cache = id;
+        let mut next_owner = owner.get();
+
+        while let Some(thread) = next_owner {
+            if thread == me {
+                let mut caches = stack_from(cache);
+                caches.push(label.describe());
+                caches.extend(chain.iter().map(|next| next.describe()));
+
+                return Err(CycleError { caches });
+            }
+
+            match graph.waiting.get(&thread) {
+                Some(wait) if chain.len() < graph.waiting.len() => {
+                    cache = wait.id;
+                    chain.push(&wait.label);
+
+                    // SAFETY: see `Graph`
+                    next_owner = unsafe { &*wait.owner }.get();
+                }
+                _ => break,
+            }
+        }
+
+        graph.waiting.insert(me, Wait { id, label, owner });
+        Ok(Waiting)
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        graph().waiting.remove(&current_thread());
+    }
+}
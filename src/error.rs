@@ -0,0 +1,105 @@
+//! Errors returned when a cache cannot hand out its value.
+
+use crate::CycleError;
+use std::error::Error;
+use std::fmt;
+
+/// the reasons why [`AtomicCache::try_get`] can fail
+///
+/// [`AtomicCache::try_get`]: ./struct.AtomicCache.html#method.try_get
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetError {
+    /// the caches being computed depend on each other
+    Cycle(CycleError),
+    /// another thread panicked while computing the value, and the cache was
+    /// configured with [`PoisonPolicy::Error`]
+    ///
+    /// [`PoisonPolicy::Error`]: ./enum.PoisonPolicy.html#variant.Error
+    Poisoned,
+}
+
+impl fmt::Display for GetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            GetError::Cycle(ref err) => err.fmt(f),
+            GetError::Poisoned => write!(f, "another thread panicked while computing the value"),
+        }
+    }
+}
+
+impl Error for GetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            GetError::Cycle(ref err) => Some(err),
+            GetError::Poisoned => None,
+        }
+    }
+}
+
+impl From<CycleError> for GetError {
+    fn from(err: CycleError) -> Self {
+        GetError::Cycle(err)
+    }
+}
+
+/// the reasons why [`TryCache::try_get`] and [`TryAtomicCache::try_get`] can
+/// fail
+///
+/// [`TryCache::try_get`]: ./struct.TryCache.html#method.try_get
+/// [`TryAtomicCache::try_get`]: ./struct.TryAtomicCache.html#method.try_get
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryGetError<E> {
+    /// the closure failed, and nothing was cached
+    Failed(E),
+    /// the caches being computed depend on each other
+    Cycle(CycleError),
+    /// another thread panicked while computing the value, and the cache was
+    /// configured with [`PoisonPolicy::Error`]. Only a [`TryAtomicCache`]
+    /// can be poisoned.
+    ///
+    /// [`PoisonPolicy::Error`]: ./enum.PoisonPolicy.html#variant.Error
+    /// [`TryAtomicCache`]: ./struct.TryAtomicCache.html
+    Poisoned,
+}
+
+impl<E> TryGetError<E> {
+    /// the error returned by the closure, if that is why the lookup failed
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "five".parse::<i32>()));
+    ///
+    /// assert!(cache.try_get().err().unwrap().failed().is_some());
+    /// ```
+    pub fn failed(self) -> Option<E> {
+        match self {
+            TryGetError::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for TryGetError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TryGetError::Failed(ref err) => err.fmt(f),
+            TryGetError::Cycle(ref err) => err.fmt(f),
+            TryGetError::Poisoned => GetError::Poisoned.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for TryGetError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            TryGetError::Failed(ref err) => Some(err),
+            TryGetError::Cycle(ref err) => Some(err),
+            TryGetError::Poisoned => None,
+        }
+    }
+}
+
+impl<E> From<CycleError> for TryGetError<E> {
+    fn from(err: CycleError) -> Self {
+        TryGetError::Cycle(err)
+    }
+}
@@ -0,0 +1,398 @@
+//! A registry of caches whose statistics can be rendered for scraping.
+
+use crate::policy::EvictionPolicy;
+use crate::stats::CacheStats;
+use crate::weigher::Weigher;
+use crate::{
+    AtomicCache, AtomicCacheMap, Cache, CacheMap, ShardedCacheMap, TryAtomicCache, TryCache,
+};
+use std::fmt::Write;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// a cache that can be registered with a [`StatsRegistry`]. Implemented by
+/// every cache type that can be [`named`], and by references and shared
+/// pointers to them.
+///
+/// [`StatsRegistry`]: ./struct.StatsRegistry.html
+/// [`named`]: ./struct.Cache.html#method.named
+pub trait StatsSource {
+    /// the name the statistics are reported under. Caches that were not
+    /// given a name fall back to their type and address.
+    fn stats_name(&self) -> String;
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// is not recording any
+    fn stats(&self) -> Option<CacheStats>;
+}
+
+impl<S: StatsSource + ?Sized> StatsSource for &S {
+    fn stats_name(&self) -> String {
+        (**self).stats_name()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        (**self).stats()
+    }
+}
+
+impl<S: StatsSource + ?Sized> StatsSource for Rc<S> {
+    fn stats_name(&self) -> String {
+        (**self).stats_name()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        (**self).stats()
+    }
+}
+
+impl<S: StatsSource + ?Sized> StatsSource for Arc<S> {
+    fn stats_name(&self) -> String {
+        (**self).stats_name()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        (**self).stats()
+    }
+}
+
+impl<T, F: Fn() -> T> StatsSource for Cache<T, F> {
+    fn stats_name(&self) -> String {
+        self.slot.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        Cache::stats(self)
+    }
+}
+
+impl<T, F: Fn() -> T> StatsSource for AtomicCache<T, F> {
+    fn stats_name(&self) -> String {
+        self.slot.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        AtomicCache::stats(self)
+    }
+}
+
+impl<T, E, F: Fn() -> Result<T, E>> StatsSource for TryCache<T, E, F> {
+    fn stats_name(&self) -> String {
+        self.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        TryCache::stats(self)
+    }
+}
+
+impl<T, E, F: Fn() -> Result<T, E>> StatsSource for TryAtomicCache<T, E, F> {
+    fn stats_name(&self) -> String {
+        self.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        TryAtomicCache::stats(self)
+    }
+}
+
+impl<K, V, F, P, W> StatsSource for CacheMap<K, V, F, P, W>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+    W: Weigher<K, V>,
+{
+    fn stats_name(&self) -> String {
+        self.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        CacheMap::stats(self)
+    }
+}
+
+impl<K, V, F, P, W> StatsSource for AtomicCacheMap<K, V, F, P, W>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+    W: Weigher<K, V>,
+{
+    fn stats_name(&self) -> String {
+        self.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        AtomicCacheMap::stats(self)
+    }
+}
+
+impl<K, V, F, P, W> StatsSource for ShardedCacheMap<K, V, F, P, W>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+    W: Weigher<K, V>,
+{
+    fn stats_name(&self) -> String {
+        self.describe()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        ShardedCacheMap::stats(self)
+    }
+}
+
+/// a set of caches whose statistics are exported together, rendered either
+/// in the Prometheus text exposition format or as JSON
+///
+/// The registry holds on to whatever is registered, which can be a borrowed
+/// cache or a shared pointer to one. Caches that are not recording
+/// statistics are left out of the output.
+/// ```
+/// # use cache::{Cache, StatsRegistry};
+/// let answer = Cache::new(Box::new(|| 55)).named("answer").record_stats();
+/// answer.get();
+///
+/// let mut registry = StatsRegistry::new();
+/// registry.register(&answer);
+///
+/// let text = registry.render_prometheus();
+/// assert!(text.contains("cache_misses_total{cache=\"answer\"} 1\n"));
+///
+/// let json = registry.render_json();
+/// assert!(json.starts_with("[{\"cache\":\"answer\",\"hits\":0,\"misses\":1,"));
+/// ```
+#[derive(Default)]
+pub struct StatsRegistry<'a> {
+    sources: Vec<Box<dyn StatsSource + 'a>>,
+}
+
+/// a metric in the Prometheus output: its name, type, help text and how to
+/// read it off a snapshot
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&CacheStats) -> String,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "cache_hits_total",
+        kind: "counter",
+        help: "Lookups that found a value.",
+        value: |stats| stats.hits.to_string(),
+    },
+    Metric {
+        name: "cache_misses_total",
+        kind: "counter",
+        help: "Lookups that had to wait for a value to be computed.",
+        value: |stats| stats.misses.to_string(),
+    },
+    Metric {
+        name: "cache_loads_total",
+        kind: "counter",
+        help: "Runs of the closure that computes values.",
+        value: |stats| stats.loads.to_string(),
+    },
+    Metric {
+        name: "cache_load_duration_seconds_total",
+        kind: "counter",
+        help: "Time spent computing values.",
+        value: |stats| stats.total_load_time.as_secs_f64().to_string(),
+    },
+    Metric {
+        name: "cache_load_duration_seconds_max",
+        kind: "gauge",
+        help: "Time taken by the slowest computation.",
+        value: |stats| stats.max_load_time.as_secs_f64().to_string(),
+    },
+    Metric {
+        name: "cache_evictions_total",
+        kind: "counter",
+        help: "Values dropped because they expired or to make room.",
+        value: |stats| stats.evictions.to_string(),
+    },
+    Metric {
+        name: "cache_invalidations_total",
+        kind: "counter",
+        help: "Values dropped because the cache was invalidated.",
+        value: |stats| stats.invalidations.to_string(),
+    },
+];
+
+impl<'a> StatsRegistry<'a> {
+    /// Constructs a new, empty StatsRegistry.
+    pub fn new() -> Self {
+        StatsRegistry::default()
+    }
+
+    /// adds a cache to the registry. Anything that implements
+    /// [`StatsSource`] can be registered, including references and
+    /// [`Arc`]s to caches.
+    /// ```
+    /// # use cache::{AtomicCache, StatsRegistry};
+    /// # use std::sync::Arc;
+    /// let calc: Box<dyn Fn() -> i32 + Send + Sync> = Box::new(|| 55);
+    /// let shared = Arc::new(AtomicCache::new(calc).named("shared").record_stats());
+    ///
+    /// let mut registry = StatsRegistry::new();
+    /// registry.register(Arc::clone(&shared));
+    ///
+    /// shared.get();
+    /// assert_eq!(registry.snapshot()[0].1.misses, 1);
+    /// ```
+    ///
+    /// [`StatsSource`]: ./trait.StatsSource.html
+    /// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+    pub fn register(&mut self, source: impl StatsSource + 'a) -> &mut Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// the name and statistics of every registered cache that is recording
+    /// statistics, in the order they were registered
+    pub fn snapshot(&self) -> Vec<(String, CacheStats)> {
+        self.sources
+            .iter()
+            .filter_map(|source| Some((source.stats_name(), source.stats()?)))
+            .collect()
+    }
+
+    /// renders the statistics of every cache in the Prometheus text
+    /// exposition format, with one time series per cache labelled with its
+    /// name
+    /// ```
+    /// # use cache::{CacheMap, StatsRegistry};
+    /// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+    /// let squares = CacheMap::new(calc).named("squares").record_stats();
+    /// squares.get(&7);
+    /// squares.get(&7);
+    ///
+    /// let mut registry = StatsRegistry::new();
+    /// registry.register(&squares);
+    ///
+    /// let text = registry.render_prometheus();
+    /// assert!(text.contains("# TYPE cache_hits_total counter\n"));
+    /// assert!(text.contains("cache_hits_total{cache=\"squares\"} 1\n"));
+    /// ```
+    pub fn render_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+
+        for metric in METRICS {
+            let _ = writeln!(out, "# HELP {} {}", metric.name, metric.help);
+            let _ = writeln!(out, "# TYPE {} {}", metric.name, metric.kind);
+
+            for (name, stats) in &snapshot {
+                let _ = writeln!(
+                    out,
+                    "{}{{cache=\"{}\"}} {}",
+                    metric.name,
+                    escape_label(name),
+                    (metric.value)(stats)
+                );
+            }
+        }
+
+        out
+    }
+
+    /// renders the statistics of every cache as a JSON array with one
+    /// object per cache. Durations are in seconds.
+    /// ```
+    /// # use cache::{Cache, StatsRegistry};
+    /// let answer = Cache::new(Box::new(|| 55)).named("answer").record_stats();
+    ///
+    /// let mut registry = StatsRegistry::new();
+    /// registry.register(&answer);
+    ///
+    /// assert_eq!(
+    ///     registry.render_json(),
+    ///     "[{\"cache\":\"answer\",\"hits\":0,\"misses\":0,\"loads\":0,\
+    ///       \"total_load_time\":0,\"max_load_time\":0,\
+    ///       \"evictions\":0,\"invalidations\":0}]"
+    /// );
+    /// ```
+    pub fn render_json(&self) -> String {
+        let caches: Vec<_> = self
+            .snapshot()
+            .iter()
+            .map(|(name, stats)| {
+                format!(
+                    "{{\"cache\":\"{}\",\"hits\":{},\"misses\":{},\"loads\":{},\
+                     \"total_load_time\":{},\"max_load_time\":{},\
+                     \"evictions\":{},\"invalidations\":{}}}",
+                    escape_json(name),
+                    stats.hits,
+                    stats.misses,
+                    stats.loads,
+                    stats.total_load_time.as_secs_f64(),
+                    stats.max_load_time.as_secs_f64(),
+                    stats.evictions,
+                    stats.invalidations
+                )
+            })
+            .collect();
+
+        format!("[{}]", caches.join(","))
+    }
+}
+
+/// escapes a label value as the Prometheus text format requires
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// escapes the contents of a JSON string
+fn escape_json(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+        assert_eq!(escape_json("a\"b\\c\u{1}"), "a\\\"b\\\\c\\u0001");
+    }
+
+    #[test]
+    fn test_caches_without_stats_are_skipped() {
+        let quiet = Cache::new(Box::new(|| 1)).named("quiet");
+        let loud = Cache::new(Box::new(|| 2)).named("loud").record_stats();
+        loud.get();
+
+        let mut registry = StatsRegistry::new();
+        registry.register(&quiet).register(&loud);
+
+        let text = registry.render_prometheus();
+        assert!(!text.contains("quiet"));
+        assert_eq!(text.matches("{cache=\"loud\"}").count(), METRICS.len());
+        assert_eq!(registry.render_json().matches("\"cache\"").count(), 1);
+    }
+}
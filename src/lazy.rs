@@ -0,0 +1,252 @@
+//! Caches whose closure runs at most once and is dropped afterwards.
+
+use crate::stats::{AtomicStats, CacheStats, Stats};
+use std::cell::{Cell, OnceCell};
+use std::sync::{Mutex, OnceLock};
+
+/// a non-thread-safe variant of [`Cache`] that takes a [`FnOnce`] closure.
+/// The closure is consumed by the first call to [`get`], so anything it
+/// captured is either moved into the value or freed straight away instead of
+/// being kept alive next to it.
+///
+/// Since the value can never be recomputed, there is no way to invalidate
+/// it, and [`get`] hands out plain references.
+///
+/// [`Cache`]: ./struct.Cache.html
+/// [`FnOnce`]: https://doc.rust-lang.org/std/ops/trait.FnOnce.html
+/// [`get`]: #method.get
+pub struct LazyCache<T, F = Box<dyn FnOnce() -> T>> {
+    init: Cell<Option<F>>,
+    data: OnceCell<T>,
+    stats: Stats,
+}
+
+impl<T, F> LazyCache<T, F>
+where
+    F: FnOnce() -> T,
+{
+    /// Constructs a new LazyCache using a closure that lazily evaluates to
+    /// the value that will be cached.
+    /// ```
+    /// # use cache::LazyCache;
+    /// let buffer = vec![1, 2, 3];
+    /// let cache = LazyCache::new(move || buffer.into_iter().sum::<i32>());
+    ///
+    /// assert_eq!(*cache.get(), 6);
+    /// ```
+    pub fn new(init: F) -> Self {
+        LazyCache {
+            init: Cell::new(Some(init)),
+            data: OnceCell::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// gets a reference to the cached value, running and dropping the
+    /// closure first if it has not been run yet
+    ///
+    /// # Panics
+    ///
+    /// Panics if the closure panicked during an earlier call, or if it calls
+    /// `get` on the same cache.
+    /// ```
+    /// # use cache::LazyCache;
+    /// let cache = LazyCache::new(|| 55);
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    pub fn get(&self) -> &T {
+        if let Some(value) = self.data.get() {
+            self.stats.hit();
+            return value;
+        }
+
+        self.stats.miss();
+        self.data.get_or_init(|| match self.init.take() {
+            Some(init) => self.stats.time(init),
+            None => panic!("LazyCache initializer either panicked or called get recursively"),
+        })
+    }
+
+    /// starts recording how often the cache is hit and how long the closure
+    /// takes, which [`stats`] then reports
+    /// ```
+    /// # use cache::LazyCache;
+    /// let cache = LazyCache::new(|| 55).record_stats();
+    ///
+    /// cache.get();
+    /// cache.get();
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.stats.enable();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::LazyCache;
+    /// let cache = LazyCache::new(|| 55);
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.stats.snapshot()
+    }
+}
+
+/// a thread-safe variant of [`LazyCache`]
+///
+/// Like [`AtomicCache`], the closure runs exactly once even if several
+/// threads call [`get`] at the same time; the others block until the value
+/// has been published.
+///
+/// [`LazyCache`]: ./struct.LazyCache.html
+/// [`AtomicCache`]: ./struct.AtomicCache.html
+/// [`get`]: #method.get
+pub struct AtomicLazyCache<T, F = Box<dyn FnOnce() -> T + Send>> {
+    init: Mutex<Option<F>>,
+    data: OnceLock<T>,
+    stats: AtomicStats,
+}
+
+impl<T, F> AtomicLazyCache<T, F>
+where
+    F: FnOnce() -> T,
+{
+    /// Constructs a new AtomicLazyCache using a closure that lazily
+    /// evaluates to the value that will be cached.
+    /// ```
+    /// # use cache::AtomicLazyCache;
+    /// let buffer = vec![1, 2, 3];
+    /// let cache = AtomicLazyCache::new(move || buffer.into_iter().sum::<i32>());
+    ///
+    /// assert_eq!(*cache.get(), 6);
+    /// ```
+    pub fn new(init: F) -> Self {
+        AtomicLazyCache {
+            init: Mutex::new(Some(init)),
+            data: OnceLock::new(),
+            stats: AtomicStats::default(),
+        }
+    }
+
+    /// gets a reference to the cached value, running and dropping the
+    /// closure first if it has not been run yet
+    ///
+    /// # Panics
+    ///
+    /// Panics if the closure panicked during an earlier call. Calling `get`
+    /// on the same cache from inside the closure deadlocks.
+    /// ```
+    /// # use cache::AtomicLazyCache;
+    /// let cache = AtomicLazyCache::new(|| 55);
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    pub fn get(&self) -> &T {
+        if let Some(value) = self.data.get() {
+            self.stats.hit();
+            return value;
+        }
+
+        self.stats.miss();
+        self.data.get_or_init(|| {
+            let init = self.init.lock().unwrap().take();
+
+            match init {
+                Some(init) => self.stats.time(init),
+                None => panic!("AtomicLazyCache initializer panicked during an earlier call"),
+            }
+        })
+    }
+
+    /// starts recording how often the cache is hit and how long the closure
+    /// takes, which [`stats`] then reports. The counters are atomic, so
+    /// recording takes no lock, but every call to [`get`] does update a
+    /// counter shared by all threads.
+    /// ```
+    /// # use cache::AtomicLazyCache;
+    /// let cache = AtomicLazyCache::new(|| 55).record_stats();
+    ///
+    /// cache.get();
+    /// cache.get();
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    /// [`get`]: #method.get
+    pub fn record_stats(mut self) -> Self {
+        self.stats.enable();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::AtomicLazyCache;
+    /// let cache = AtomicLazyCache::new(|| 55);
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.stats.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_closure_is_dropped_after_first_get() {
+        let resource = Arc::new(());
+        let captured = Arc::clone(&resource);
+
+        let cache = LazyCache::new(move || {
+            let _resource = captured;
+            55
+        });
+
+        assert_eq!(Arc::strong_count(&resource), 2);
+        assert_eq!(*cache.get(), 55);
+        assert_eq!(Arc::strong_count(&resource), 1);
+    }
+
+    #[test]
+    fn test_atomic_closure_runs_once() {
+        let resource = Arc::new(());
+        let captured = Arc::clone(&resource);
+
+        let cache = Arc::new(AtomicLazyCache::new(move || {
+            thread::sleep(std::time::Duration::from_millis(20));
+            Arc::strong_count(&captured)
+        }));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || *cache.get())
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 2);
+        }
+
+        assert_eq!(Arc::strong_count(&resource), 1);
+    }
+}
@@ -1,151 +1,1285 @@
 //! Thread-safe and non-thread-safe variants of lazily evaluated caches.
+//!
+//! With the `tracing` feature enabled, caches emit debug-level spans through
+//! the [`tracing`] crate: `init` while the closure computes a missing value,
+//! `recompute` while it replaces an expired or refreshed one, and `wait`
+//! while a thread blocks on another thread's computation. Every span has a
+//! `cache` field holding the name given to the cache with `named`, or a
+//! description of its type and address otherwise.
+//!
+//! [`tracing`]: https://docs.rs/tracing
 
 #![deny(missing_docs)]
 
-use std::cell::{Ref, RefCell};
-use std::ops::Deref;
-use std::sync::{RwLock, RwLockReadGuard};
+mod admission;
+mod async_cache;
+mod cycle;
+mod error;
+mod export;
+mod lazy;
+mod listener;
+mod map;
+mod policy;
+mod sharded;
+mod slot;
+mod stats;
+mod store;
+mod trace;
+mod try_cache;
+mod weigher;
+
+pub use crate::async_cache::AsyncCache;
+pub use crate::cycle::CycleError;
+pub use crate::error::{GetError, TryGetError};
+pub use crate::export::{StatsRegistry, StatsSource};
+pub use crate::lazy::{AtomicLazyCache, LazyCache};
+pub use crate::listener::{CacheListener, RemovalCause};
+pub use crate::map::{AtomicCacheMap, AtomicCacheMapRef, CacheMap, CacheMapRef};
+pub use crate::policy::{ArcPolicy, ClockPolicy, EvictionPolicy, FifoPolicy, LfuPolicy, LruPolicy};
+pub use crate::sharded::ShardedCacheMap;
+pub use crate::stats::CacheStats;
+pub use crate::try_cache::{TryAtomicCache, TryCache};
+pub use crate::weigher::{ByteWeigher, UnitWeigher, Weigher};
+
+use crate::slot::{AtomicSlot, Entry, InitError, ReadGuard, Slot, WriteGuard};
+use crate::trace::Span;
+use std::cell::{Ref, RefMut};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// a non-thread-safe implementation of a lazily evaluated expression. For a
 /// thread-safe variant, use [`AtomicCache`].
-/// 
+///
+/// The closure is stored inline as `F`, which defaults to a boxed closure so
+/// that `Cache<T>` can still be named without spelling out the closure type.
+///
 /// [`AtomicCache`]: ./struct.AtomicCache.html
-pub struct Cache<T> {
-    calc: Box<Fn() -> T>,
-    data: RefCell<Option<T>>,
+pub struct Cache<T, F = Box<dyn Fn() -> T>> {
+    calc: F,
+    slot: Slot<T>,
+}
+
+impl<T, F> Cache<T, F>
+where
+    F: Fn() -> T,
+{
+    /// Constructs a new Cache using a closure that lazily evaluates to the
+    /// value that will be cached. The closure may borrow local data.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    ///
+    /// let words = vec!["a", "b", "c"];
+    /// let cache = Cache::new(|| words.len());
+    ///
+    /// assert_eq!(*cache.get(), 3);
+    /// ```
+    pub fn new(calc: F) -> Self {
+        Cache {
+            calc,
+            slot: Slot::new(None),
+        }
+    }
+
+    /// Constructs a new Cache whose value expires `ttl` after it was
+    /// computed. A call to [`get`] on an expired cache recomputes the value.
+    ///
+    /// Recomputing an expired value panics if a [`CacheRef`] to the old
+    /// value is still alive, just like [`invalidate`] does.
+    /// ```
+    /// # use cache::Cache;
+    /// # use std::cell::Cell;
+    /// # use std::rc::Rc;
+    /// # use std::time::Duration;
+    /// let calls = Rc::new(Cell::new(0));
+    /// let counter = Rc::clone(&calls);
+    /// let cache = Cache::with_ttl(
+    ///     Box::new(move || counter.set(counter.get() + 1)),
+    ///     Duration::from_millis(10),
+    /// );
+    ///
+    /// cache.get();
+    /// cache.get();
+    /// assert_eq!(calls.get(), 1);
+    ///
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// cache.get();
+    /// assert_eq!(calls.get(), 2);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`invalidate`]: #method.invalidate
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn with_ttl(calc: F, ttl: Duration) -> Self {
+        Cache {
+            calc,
+            slot: Slot::new(Some(ttl)),
+        }
+    }
+
+    /// gets a reference to the cached value, computing it first if it
+    /// does not exist or has expired
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the cycle if the closure, directly or
+    /// through other caches, calls `get` on this same cache. Use [`try_get`]
+    /// to handle that case as an error instead.
+    ///
+    /// [`try_get`]: #method.try_get
+    pub fn get(&self) -> CacheRef<'_, T> {
+        match self.try_get() {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// gets a reference to the cached value like [`get`], but returns a
+    /// [`CycleError`] instead of panicking if the closure ends up asking this
+    /// same cache for its value
+    /// ```
+    /// # use cache::Cache;
+    /// # use std::cell::OnceCell;
+    /// # use std::rc::Rc;
+    /// let slot = Rc::new(OnceCell::<Cache<String>>::new());
+    /// let weak = Rc::downgrade(&slot);
+    /// let cache = slot.get_or_init(|| {
+    ///     let calc: Box<dyn Fn() -> String> = Box::new(move || {
+    ///         let slot = weak.upgrade().unwrap();
+    ///         let value = slot.get().unwrap().try_get();
+    ///
+    ///         value.map(|v| v.clone()).unwrap_or_else(|err| err.to_string())
+    ///     });
+    ///
+    ///     Cache::new(calc).named("config")
+    /// });
+    ///
+    /// assert_eq!(
+    ///     *cache.get(),
+    ///     "cycle detected while initializing caches: config -> config"
+    /// );
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn try_get(&self) -> Result<CacheRef<'_, T>, CycleError> {
+        let calc = &self.calc;
+
+        match self.slot.get_or_try_init(|| Ok::<_, Infallible>(calc())) {
+            Ok(value) => Ok(value),
+            Err(InitError::Cycle(err)) => Err(err),
+            Err(InitError::Poisoned) => unreachable!("a Cache has no lock to poison"),
+            Err(InitError::Failed(never)) => match never {},
+        }
+    }
+
+    /// gets a mutable reference to the cached value, computing it first if
+    /// it does not exist or has expired
+    ///
+    /// # Panics
+    ///
+    /// Panics if another [`CacheRef`] or [`CacheRefMut`] to the value is
+    /// still alive, or if the closure ends up calling `get_mut` on this same
+    /// cache.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| vec![1, 2]));
+    ///
+    /// cache.get_mut().push(3);
+    /// assert_eq!(*cache.get(), [1, 2, 3]);
+    /// ```
+    ///
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    /// [`CacheRefMut`]: ./struct.CacheRefMut.html
+    pub fn get_mut(&self) -> CacheRefMut<'_, T> {
+        let calc = &self.calc;
+
+        match self
+            .slot
+            .get_mut_or_try_init(|| Ok::<_, Infallible>(calc()))
+        {
+            Ok(value) => value,
+            Err(InitError::Cycle(err)) => panic!("{}", err),
+            Err(InitError::Poisoned) => unreachable!("a Cache has no lock to poison"),
+            Err(InitError::Failed(never)) => match never {},
+        }
+    }
+
+    /// gets a mutable reference to the cached value through a unique
+    /// reference to the cache, computing it first if it does not exist or
+    /// has expired. Unlike [`get_mut`], this needs no runtime borrow checks
+    /// and cannot panic.
+    /// ```
+    /// # use cache::Cache;
+    /// let mut cache = Cache::new(Box::new(|| vec![1, 2]));
+    ///
+    /// cache.force_mut().push(3);
+    /// assert_eq!(*cache.get(), [1, 2, 3]);
+    /// ```
+    ///
+    /// [`get_mut`]: #method.get_mut
+    pub fn force_mut(&mut self) -> &mut T {
+        let calc = &self.calc;
+
+        self.slot.force_mut(calc)
+    }
+
+    /// consumes the cache, returning the value it holds, if any. The value
+    /// is returned even if it has expired.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    /// assert_eq!(cache.into_inner(), None);
+    ///
+    /// let cache = Cache::new(Box::new(|| 55));
+    /// cache.get();
+    /// assert_eq!(cache.into_inner(), Some(55));
+    /// ```
+    pub fn into_inner(self) -> Option<T> {
+        self.slot.into_inner()
+    }
+
+    /// consumes the cache, returning its value and computing it first if
+    /// it does not exist or has expired
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(cache.force_into_inner(), 55);
+    /// ```
+    pub fn force_into_inner(mut self) -> T {
+        self.force_mut();
+        self.into_inner().unwrap()
+    }
+
+    /// consumes the cache, returning both its closure and the value it
+    /// holds, if any. The value is returned even if it has expired.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(|| 55);
+    /// cache.get();
+    ///
+    /// let (calc, value) = cache.into_parts();
+    /// assert_eq!(value, Some(55));
+    /// assert_eq!(calc(), 55);
+    /// ```
+    pub fn into_parts(self) -> (F, Option<T>) {
+        (self.calc, self.slot.into_inner())
+    }
+
+    /// gives the cache a name, which is used to identify it in a
+    /// [`CycleError`]
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55)).named("answer");
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.slot.set_name(name.into());
+        self
+    }
+
+    /// starts recording how often the cache is hit and how long the closure
+    /// takes, which [`stats`] then reports
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55)).record_stats();
+    ///
+    /// cache.get();
+    /// cache.get();
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.slot.enable_stats();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.slot.stats()
+    }
+
+    /// hands every event of the cache to `listener`: the closure starting
+    /// and finishing, hits, and the value being replaced, invalidated or
+    /// dropped after it expired
+    /// ```
+    /// # use cache::{Cache, CacheListener, RemovalCause};
+    /// # use std::cell::RefCell;
+    /// # use std::rc::Rc;
+    /// struct Removals(Rc<RefCell<Vec<(i32, RemovalCause)>>>);
+    ///
+    /// impl CacheListener<(), i32> for Removals {
+    ///     fn on_removal(&self, _: &(), value: &i32, cause: RemovalCause) {
+    ///         self.0.borrow_mut().push((*value, cause));
+    ///     }
+    /// }
+    ///
+    /// let removals = Rc::new(RefCell::new(Vec::new()));
+    /// let cache = Cache::new(Box::new(|| 55)).listened_by(Removals(Rc::clone(&removals)));
+    ///
+    /// cache.get();
+    /// cache.set(10);
+    /// cache.invalidate();
+    ///
+    /// assert_eq!(
+    ///     *removals.borrow(),
+    ///     [(55, RemovalCause::Replaced), (10, RemovalCause::Invalidated)]
+    /// );
+    /// ```
+    pub fn listened_by(mut self, listener: impl CacheListener<(), T> + 'static) -> Self {
+        self.slot.set_listener(Box::new(listener));
+        self
+    }
+
+    /// drops the cached value, if any, so that the next call to [`get`]
+    /// recomputes it
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`CacheRef`] to the cached value is still alive.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// cache.invalidate();
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn invalidate(&self) {
+        self.take();
+    }
+
+    /// removes the cached value, if any, and returns it. The next call to
+    /// [`get`] recomputes the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`CacheRef`] to the cached value is still alive.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(cache.take(), None);
+    /// cache.get();
+    /// assert_eq!(cache.take(), Some(55));
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn take(&self) -> Option<T> {
+        self.slot.take()
+    }
+
+    /// replaces the cached value with `value`, without running the closure
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`CacheRef`] to the cached value is still alive.
+    /// ```
+    /// # use cache::Cache;
+    /// let cache = Cache::new(Box::new(|| 55));
+    ///
+    /// cache.set(10);
+    /// assert_eq!(*cache.get(), 10);
+    /// ```
+    ///
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn set(&self, value: T) {
+        self.slot.set(value);
+    }
+}
+
+/// A non-thread-safe reference to the cached value stored in a [`Cache`],
+/// or to a part of it. Constructed using the [`get`] method on a [`Cache`].
+/// Consists of a thin wrapper around a [`RefCell`] reference.
+///
+/// [`Cache`]: ./struct.Cache.html
+/// [`get`]: ./struct.Cache.html#method.get
+/// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+pub struct CacheRef<'a, T: ?Sized>(Ref<'a, T>);
+
+impl<'a, T> CacheRef<'a, T> {
+    fn new(r: Ref<'a, Option<Entry<T>>>) -> Self {
+        CacheRef(Ref::map(r, |data| &data.as_ref().unwrap().value))
+    }
+}
+
+impl<'a, T: ?Sized> CacheRef<'a, T> {
+    /// makes a new CacheRef for a part of the referenced value, such as a
+    /// field of a struct
+    ///
+    /// This is an associated function that needs to be used as
+    /// `CacheRef::map(...)`, so that it does not get in the way of a method
+    /// with the same name on the cached value.
+    /// ```
+    /// # use cache::{Cache, CacheRef};
+    /// struct Config {
+    ///     name: String,
+    /// }
+    ///
+    /// fn name(cache: &Cache<Config>) -> CacheRef<'_, str> {
+    ///     CacheRef::map(cache.get(), |config| config.name.as_str())
+    /// }
+    ///
+    /// let calc: Box<dyn Fn() -> Config> = Box::new(|| Config { name: "cache".into() });
+    /// let cache = Cache::new(calc);
+    ///
+    /// assert_eq!(&*name(&cache), "cache");
+    /// ```
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> CacheRef<'a, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        CacheRef(Ref::map(this.0, f))
+    }
+
+    /// makes a new CacheRef for an optional part of the referenced value.
+    /// If the closure returns `None`, the original reference is handed back.
+    ///
+    /// Like [`map`], this is an associated function that needs to be used as
+    /// `CacheRef::filter_map(...)`.
+    /// ```
+    /// # use cache::{Cache, CacheRef};
+    /// let cache = Cache::new(Box::new(|| vec![1, 2, 3]));
+    ///
+    /// let second = CacheRef::filter_map(cache.get(), |v| v.get(1));
+    /// assert_eq!(*second.ok().unwrap(), 2);
+    ///
+    /// let tenth = CacheRef::filter_map(cache.get(), |v| v.get(9));
+    /// assert_eq!(tenth.err().unwrap().len(), 3);
+    /// ```
+    ///
+    /// [`map`]: #method.map
+    pub fn filter_map<U: ?Sized, F>(this: Self, f: F) -> Result<CacheRef<'a, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        Ref::filter_map(this.0, f).map(CacheRef).map_err(CacheRef)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for CacheRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A non-thread-safe mutable reference to the cached value stored in a
+/// [`Cache`]. Constructed using the [`get_mut`] method on a [`Cache`].
+/// Consists of a thin wrapper around a mutable [`RefCell`] reference.
+///
+/// [`Cache`]: ./struct.Cache.html
+/// [`get_mut`]: ./struct.Cache.html#method.get_mut
+/// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+pub struct CacheRefMut<'a, T>(RefMut<'a, Option<Entry<T>>>);
+
+impl<'a, T> CacheRefMut<'a, T> {
+    fn new(r: RefMut<'a, Option<Entry<T>>>) -> Self {
+        CacheRefMut(r)
+    }
+}
+
+impl<'a, T> Deref for CacheRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.as_ref().unwrap().value
+    }
+}
+
+impl<'a, T> DerefMut for CacheRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0.as_mut().unwrap().value
+    }
 }
 
-impl<T> Cache<T> {
-    /// Constructs a new Cache using a boxed closure that lazily evaluates
-    /// to the value that will be cached.
+/// a thread-safe variant of [`Cache`]
+///
+/// The closure is stored inline as `F`, which defaults to a boxed closure.
+/// An AtomicCache can be shared between threads as long as `F` is `Sync`.
+///
+/// The value is guarded like a read-write lock whose read side is split
+/// into one counter per core, so threads reading an already computed value
+/// at the same time never write to the same cache line. Writers, such as
+/// [`invalidate`] or a recomputation, still wait for every reader to leave.
+///
+/// [`Cache`]: ./struct.Cache.html
+/// [`invalidate`]: #method.invalidate
+pub struct AtomicCache<T, F = Box<dyn Fn() -> T + Send + Sync>> {
+    calc: F,
+    slot: AtomicSlot<T>,
+}
+
+impl<T, F> AtomicCache<T, F>
+where
+    F: Fn() -> T,
+{
+    /// Constructs a new AtomicCache using a closure that lazily evaluates
+    /// to the value that will be cached. The closure may borrow local data.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    ///
+    /// let words = vec!["a", "b", "c"];
+    /// let cache = AtomicCache::new(|| words.len());
+    ///
+    /// std::thread::scope(|s| {
+    ///     s.spawn(|| assert_eq!(*cache.get(), 3));
+    /// });
+    /// ```
+    pub fn new(calc: F) -> Self {
+        AtomicCache {
+            calc,
+            slot: AtomicSlot::new(None),
+        }
+    }
+
+    /// Constructs a new AtomicCache whose value expires `ttl` after it was
+    /// computed. A call to [`get`] on an expired cache recomputes the value,
+    /// blocking until every outstanding [`AtomicCacheRef`] to the old value
+    /// has been dropped.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    /// let counter = Arc::clone(&calls);
+    /// let cache = AtomicCache::with_ttl(
+    ///     Box::new(move || counter.fetch_add(1, Ordering::SeqCst)),
+    ///     Duration::from_millis(10),
+    /// );
+    ///
+    /// assert_eq!(*cache.get(), 0);
+    /// assert_eq!(*cache.get(), 0);
+    ///
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// assert_eq!(*cache.get(), 1);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn with_ttl(calc: F, ttl: Duration) -> Self {
+        AtomicCache {
+            calc,
+            slot: AtomicSlot::new(Some(ttl)),
+        }
+    }
+
+    /// gets a reference to the cached value, computing it first if it
+    /// does not exist or has expired
+    ///
+    /// The value is computed exactly once: if several threads call `get` on
+    /// an empty cache at the same time, the first one to take the lock runs
+    /// the closure while the others block until the value has been
+    /// published, and then read it.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the cycle if the closure, directly or
+    /// through other caches, calls `get` on this same cache, or if waiting
+    /// for another thread's computation would deadlock because that thread
+    /// is itself waiting on a cache the current thread is computing. Use
+    /// [`try_get`] to handle those cases as an error instead.
+    ///
+    /// Also panics if another thread panicked while computing the value,
+    /// unless the cache was configured to recover with [`on_poison`].
+    ///
+    /// [`try_get`]: #method.try_get
+    /// [`on_poison`]: #method.on_poison
+    pub fn get(&self) -> AtomicCacheRef<'_, T> {
+        match self.try_get() {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// gets a reference to the cached value like [`get`], but returns a
+    /// [`GetError`] instead of panicking or deadlocking if the caches being
+    /// computed end up depending on each other, or if the cache was poisoned
+    /// and configured with [`PoisonPolicy::Error`]
+    /// ```
+    /// # use cache::AtomicCache;
+    /// # use std::sync::{Arc, OnceLock};
+    /// type Calc = Box<dyn Fn() -> String + Send + Sync>;
+    ///
+    /// let slot = Arc::new(OnceLock::<AtomicCache<String>>::new());
+    /// let weak = Arc::downgrade(&slot);
+    /// let cache = slot.get_or_init(|| {
+    ///     let calc: Calc = Box::new(move || {
+    ///         let slot = weak.upgrade().unwrap();
+    ///         let value = slot.get().unwrap().try_get();
+    ///
+    ///         value.map(|v| v.clone()).unwrap_or_else(|err| err.to_string())
+    ///     });
+    ///
+    ///     AtomicCache::new(calc).named("config")
+    /// });
+    ///
+    /// assert_eq!(
+    ///     *cache.get(),
+    ///     "cycle detected while initializing caches: config -> config"
+    /// );
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`GetError`]: ./enum.GetError.html
+    /// [`PoisonPolicy::Error`]: ./enum.PoisonPolicy.html#variant.Error
+    pub fn try_get(&self) -> Result<AtomicCacheRef<'_, T>, GetError> {
+        let calc = &self.calc;
+
+        match self.slot.get_or_try_init(|| Ok::<_, Infallible>(calc())) {
+            Ok(value) => Ok(value),
+            Err(InitError::Cycle(err)) => Err(GetError::Cycle(err)),
+            Err(InitError::Poisoned) => Err(GetError::Poisoned),
+            Err(InitError::Failed(never)) => match never {},
+        }
+    }
+
+    /// gets a mutable reference to the cached value, computing it first if
+    /// it does not exist or has expired. The returned guard holds the lock
+    /// and keeps readers out, so every other reader and writer blocks until
+    /// it is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`get`].
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| vec![1, 2]));
+    ///
+    /// cache.get_mut().push(3);
+    /// assert_eq!(*cache.get(), [1, 2, 3]);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    pub fn get_mut(&self) -> AtomicCacheRefMut<'_, T> {
+        let calc = &self.calc;
+
+        match self
+            .slot
+            .get_mut_or_try_init(|| Ok::<_, Infallible>(calc()))
+        {
+            Ok(write) => AtomicCacheRefMut::new(write),
+            Err(InitError::Cycle(err)) => panic!("{}", err),
+            Err(InitError::Poisoned) => panic!("{}", GetError::Poisoned),
+            Err(InitError::Failed(never)) => match never {},
+        }
+    }
+
+    /// gets a mutable reference to the cached value through a unique
+    /// reference to the cache, computing it first if it does not exist or
+    /// has expired. Since no other thread can be using the cache, this
+    /// takes no locks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a thread panicked while running the closure, unless the
+    /// cache was configured with [`PoisonPolicy::Recover`].
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let mut cache = AtomicCache::new(Box::new(|| vec![1, 2]));
+    ///
+    /// cache.force_mut().push(3);
+    /// assert_eq!(*cache.get(), [1, 2, 3]);
+    /// ```
+    ///
+    /// [`PoisonPolicy::Recover`]: ./enum.PoisonPolicy.html#variant.Recover
+    pub fn force_mut(&mut self) -> &mut T {
+        let calc = &self.calc;
+
+        self.slot.force_mut(calc)
+    }
+
+    /// consumes the cache, returning the value it holds, if any. The value
+    /// is returned even if it has expired.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a thread panicked while running the closure, unless the
+    /// cache was configured with [`PoisonPolicy::Recover`], in which case
+    /// the value is discarded.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    /// assert_eq!(cache.into_inner(), None);
+    ///
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    /// cache.get();
+    /// assert_eq!(cache.into_inner(), Some(55));
+    /// ```
+    ///
+    /// [`PoisonPolicy::Recover`]: ./enum.PoisonPolicy.html#variant.Recover
+    pub fn into_inner(self) -> Option<T> {
+        self.slot.into_inner()
+    }
+
+    /// consumes the cache, returning its value and computing it first if
+    /// it does not exist or has expired
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`into_inner`].
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(cache.force_into_inner(), 55);
+    /// ```
+    ///
+    /// [`into_inner`]: #method.into_inner
+    pub fn force_into_inner(mut self) -> T {
+        self.force_mut();
+        self.into_inner().unwrap()
+    }
+
+    /// consumes the cache, returning both its closure and the value it
+    /// holds, if any. The value is returned even if it has expired.
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`into_inner`].
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(|| 55);
+    /// cache.get();
+    ///
+    /// let (calc, value) = cache.into_parts();
+    /// assert_eq!(value, Some(55));
+    /// assert_eq!(calc(), 55);
+    /// ```
+    ///
+    /// [`into_inner`]: #method.into_inner
+    pub fn into_parts(self) -> (F, Option<T>) {
+        (self.calc, self.slot.into_inner())
+    }
+
+    /// chooses what happens when a thread panics while running the closure,
+    /// which poisons the lock around the value. Defaults to
+    /// [`PoisonPolicy::Panic`].
+    /// ```
+    /// # use cache::{AtomicCache, PoisonPolicy};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// let calls = AtomicUsize::new(0);
+    /// let cache = AtomicCache::new(|| {
+    ///     if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+    ///         panic!("first attempt fails");
+    ///     }
+    ///
+    ///     55
+    /// })
+    /// .on_poison(PoisonPolicy::Recover);
+    ///
+    /// std::thread::scope(|s| {
+    ///     assert!(s.spawn(|| *cache.get()).join().is_err());
+    /// });
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    ///
+    /// [`PoisonPolicy::Panic`]: ./enum.PoisonPolicy.html#variant.Panic
+    pub fn on_poison(mut self, policy: PoisonPolicy) -> Self {
+        self.slot.set_poison_policy(policy);
+        self
+    }
+
+    /// gives the cache a name, which is used to identify it in a
+    /// [`CycleError`]
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55)).named("answer");
+    ///
+    /// assert_eq!(*cache.get(), 55);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.slot.set_name(name.into());
+        self
+    }
+
+    /// starts recording how often the cache is hit and how long the closure
+    /// takes, which [`stats`] then reports. The counters are
+    /// atomic, so recording takes no lock, but every lookup does update a
+    /// counter shared by all threads
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55)).record_stats();
+    ///
+    /// cache.get();
+    /// cache.get();
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.slot.enable_stats();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.slot.stats()
+    }
+
+    /// hands every event of the cache to `listener`, like
+    /// [`Cache::listened_by`]. Events are reported while the cache is
+    /// locked, so the listener must not use the cache itself.
     /// ```
-    /// # use cache::Cache;
-    /// let cache = Cache::new(Box::new(|| 55));
+    /// # use cache::{AtomicCache, CacheListener};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// struct Loads(Arc<AtomicUsize>);
     ///
-    /// assert_eq!(*cache.get(), 55);
+    /// impl CacheListener<(), i32> for Loads {
+    ///     fn on_compute_finish(&self, _: &(), _: &i32, _: Duration) {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let loads = Arc::new(AtomicUsize::new(0));
+    /// let cache = AtomicCache::new(Box::new(|| 55)).listened_by(Loads(Arc::clone(&loads)));
+    ///
+    /// cache.get();
+    /// cache.get();
+    ///
+    /// assert_eq!(loads.load(Ordering::Relaxed), 1);
     /// ```
-    pub fn new(calc: Box<Fn() -> T>) -> Self {
-        Cache {
-            calc,
-            data: RefCell::new(None),
-        }
+    ///
+    /// [`Cache::listened_by`]: ./struct.Cache.html#method.listened_by
+    pub fn listened_by(
+        mut self,
+        listener: impl CacheListener<(), T> + Send + Sync + 'static,
+    ) -> Self {
+        self.slot.set_listener(Box::new(listener));
+        self
     }
 
-    /// gets a reference to the cached value, computing it first if it
-    /// does not exist
+    /// drops the cached value, if any, so that the next call to [`get`]
+    /// recomputes it
+    ///
+    /// Blocks until every outstanding [`AtomicCacheRef`] has been dropped, so
+    /// calling this while the current thread still holds one will deadlock.
+    /// Since the old value is discarded, this also clears any poisoning left
+    /// behind by a panic in the closure.
     /// ```
-    /// # use cache::Cache;
-    /// let cache = Cache::new(Box::new(|| 55));
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
     ///
     /// assert_eq!(*cache.get(), 55);
+    /// cache.invalidate();
+    /// assert_eq!(*cache.get(), 55);
     /// ```
-    pub fn get(&self) -> CacheRef<T> {
-        if self.data.borrow().is_none() {
-            let calc = &self.calc;
-            let data = calc();
+    ///
+    /// [`get`]: #method.get
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn invalidate(&self) {
+        self.take();
+    }
 
-            self.data.replace(Some(data));
-        }
+    /// removes the cached value, if any, and returns it. The next call to
+    /// [`get`] recomputes the value.
+    ///
+    /// Blocks until every outstanding [`AtomicCacheRef`] has been dropped, so
+    /// calling this while the current thread still holds one will deadlock.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    ///
+    /// assert_eq!(cache.take(), None);
+    /// cache.get();
+    /// assert_eq!(cache.take(), Some(55));
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn take(&self) -> Option<T> {
+        self.slot.take()
+    }
 
-        CacheRef::new(self.data.borrow())
+    /// replaces the cached value with `value`, without running the closure
+    ///
+    /// Blocks until every outstanding [`AtomicCacheRef`] has been dropped, so
+    /// calling this while the current thread still holds one will deadlock.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// let cache = AtomicCache::new(Box::new(|| 55));
+    ///
+    /// cache.set(10);
+    /// assert_eq!(*cache.get(), 10);
+    /// ```
+    ///
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn set(&self, value: T) {
+        self.slot.set(value);
     }
 }
 
-/// A non-thread-safe reference to the cached value stored in a [`Cache`].
-/// Constructed using the [`get`] method on a [`Cache`].
-/// Consists of a thin wrapper around a [`RefCell`] reference.
+impl<T, F> AtomicCache<Arc<T>, F>
+where
+    F: Fn() -> Arc<T>,
+{
+    /// gets a clone of the cached [`Arc`], computing the value first if it
+    /// does not exist or has expired
+    ///
+    /// Unlike [`get`], the reader is counted out before this returns, so the
+    /// returned value can be kept around for as long as needed without
+    /// holding up [`invalidate`], [`set`] or a recomputation after the value
+    /// has expired. Readers that hold on to an old value keep seeing it,
+    /// while later calls see the new one.
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`get`].
+    /// ```
+    /// # use cache::AtomicCache;
+    /// # use std::sync::Arc;
+    /// let cache = AtomicCache::new(Box::new(|| Arc::new(55)));
+    ///
+    /// let old = cache.get_arc();
+    /// cache.set(Arc::new(10));
+    ///
+    /// assert_eq!(*old, 55);
+    /// assert_eq!(*cache.get_arc(), 10);
+    /// ```
+    ///
+    /// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+    /// [`get`]: #method.get
+    /// [`invalidate`]: #method.invalidate
+    /// [`set`]: #method.set
+    pub fn get_arc(&self) -> Arc<T> {
+        Arc::clone(&self.get())
+    }
+
+    /// gets a clone of the cached [`Arc`] like [`get_arc`], but returns a
+    /// [`GetError`] in the same cases as [`try_get`]
+    /// ```
+    /// # use cache::AtomicCache;
+    /// # use std::sync::Arc;
+    /// let cache = AtomicCache::new(Box::new(|| Arc::new(55)));
+    ///
+    /// assert_eq!(*cache.try_get_arc().unwrap(), 55);
+    /// ```
+    ///
+    /// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+    /// [`get_arc`]: #method.get_arc
+    /// [`GetError`]: ./enum.GetError.html
+    /// [`try_get`]: #method.try_get
+    pub fn try_get_arc(&self) -> Result<Arc<T>, GetError> {
+        self.try_get().map(|value| Arc::clone(&value))
+    }
+
+    /// recomputes the value and swaps it in, returning the new value
+    ///
+    /// The closure runs without holding any lock, so other threads keep
+    /// reading the old value in the meantime instead of blocking on the
+    /// computation. Only the swap itself waits for outstanding
+    /// [`AtomicCacheRef`]s to be dropped; values handed out by [`get_arc`]
+    /// are not waited on.
+    /// ```
+    /// # use cache::AtomicCache;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// let calls = AtomicUsize::new(0);
+    /// let cache = AtomicCache::new(|| Arc::new(calls.fetch_add(1, Ordering::SeqCst)));
+    ///
+    /// let old = cache.get_arc();
+    /// assert_eq!(*cache.refresh(), 1);
+    ///
+    /// assert_eq!(*old, 0);
+    /// assert_eq!(*cache.get_arc(), 1);
+    /// ```
+    ///
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    /// [`get_arc`]: #method.get_arc
+    pub fn refresh(&self) -> Arc<T> {
+        let value = {
+            let _span = Span::recompute(|| self.slot.describe());
+            self.slot.compute(&self.calc)
+        };
+        self.set(Arc::clone(&value));
+
+        value
+    }
+}
+
+/// A thread-safe reference to the cached value stored in an [`AtomicCache`].
+/// Constructed using the [`get`] method on an [`AtomicCache`].
+/// Holds the cache's read side, counting the current thread in as a reader
+/// until it is dropped.
 ///
-/// [`Cache`]: ./struct.Cache.html
-/// [`get`]: ./struct.Cache.html#method.get
-/// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
-pub struct CacheRef<'a, T>(Ref<'a, Option<T>>);
+/// [`AtomicCache`]: ./struct.AtomicCache.html
+/// [`get`]: ./struct.AtomicCache.html#method.get
+pub struct AtomicCacheRef<'a, T>(ReadGuard<'a, T>);
 
-impl<'a, T> CacheRef<'a, T> {
-    fn new(r: Ref<'a, Option<T>>) -> Self {
-        CacheRef(r)
+impl<'a, T> AtomicCacheRef<'a, T> {
+    fn new(r: ReadGuard<'a, T>) -> Self {
+        AtomicCacheRef(r)
+    }
+
+    /// makes a new [`MappedAtomicCacheRef`] for a part of the referenced
+    /// value, such as a field of a struct. The reader stays counted in until
+    /// the new reference is dropped.
+    ///
+    /// This is an associated function that needs to be used as
+    /// `AtomicCacheRef::map(...)`, so that it does not get in the way of a
+    /// method with the same name on the cached value.
+    /// ```
+    /// # use cache::{AtomicCache, AtomicCacheRef, MappedAtomicCacheRef};
+    /// struct Config {
+    ///     name: String,
+    /// }
+    ///
+    /// fn name(cache: &AtomicCache<Config>) -> MappedAtomicCacheRef<'_, Config, str> {
+    ///     AtomicCacheRef::map(cache.get(), |config| config.name.as_str())
+    /// }
+    ///
+    /// let calc: Box<dyn Fn() -> Config + Send + Sync> = Box::new(|| Config { name: "cache".into() });
+    /// let cache = AtomicCache::new(calc);
+    ///
+    /// assert_eq!(&*name(&cache), "cache");
+    /// ```
+    ///
+    /// [`MappedAtomicCacheRef`]: ./struct.MappedAtomicCacheRef.html
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedAtomicCacheRef<'a, T, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let value = NonNull::from(f(&this));
+        MappedAtomicCacheRef::new(this.0, value)
+    }
+
+    /// makes a new [`MappedAtomicCacheRef`] for an optional part of the
+    /// referenced value. If the closure returns `None`, the original
+    /// reference is handed back.
+    ///
+    /// Like [`map`], this is an associated function that needs to be used as
+    /// `AtomicCacheRef::filter_map(...)`.
+    /// ```
+    /// # use cache::{AtomicCache, AtomicCacheRef};
+    /// let cache = AtomicCache::new(Box::new(|| vec![1, 2, 3]));
+    ///
+    /// let second = AtomicCacheRef::filter_map(cache.get(), |v| v.get(1));
+    /// assert_eq!(*second.ok().unwrap(), 2);
+    ///
+    /// let tenth = AtomicCacheRef::filter_map(cache.get(), |v| v.get(9));
+    /// assert_eq!(tenth.err().unwrap().len(), 3);
+    /// ```
+    ///
+    /// [`MappedAtomicCacheRef`]: ./struct.MappedAtomicCacheRef.html
+    /// [`map`]: #method.map
+    pub fn filter_map<U: ?Sized, F>(
+        this: Self,
+        f: F,
+    ) -> Result<MappedAtomicCacheRef<'a, T, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        match f(&this).map(NonNull::from) {
+            Some(value) => Ok(MappedAtomicCacheRef::new(this.0, value)),
+            None => Err(this),
+        }
     }
 }
 
-impl<'a, T> Deref for CacheRef<'a, T> {
+impl<'a, T> Deref for AtomicCacheRef<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref().unwrap()
+        &self.0.data().as_ref().unwrap().value
     }
 }
 
-/// a thread-safe variant of [`Cache`]
+/// A thread-safe reference to a part of the cached value stored in an
+/// [`AtomicCache`]. Constructed using [`AtomicCacheRef::map`] or
+/// [`AtomicCacheRef::filter_map`]. Consists of the read side held by the
+/// original reference along with a pointer to the part it was narrowed to.
 ///
-/// [`Cache`]: ./struct.Cache.html
-pub struct AtomicCache<T> {
-    calc: Box<Fn() -> T + Send + Sync>,
-    data: RwLock<Option<T>>,
+/// [`AtomicCache`]: ./struct.AtomicCache.html
+/// [`AtomicCacheRef::map`]: ./struct.AtomicCacheRef.html#method.map
+/// [`AtomicCacheRef::filter_map`]: ./struct.AtomicCacheRef.html#method.filter_map
+pub struct MappedAtomicCacheRef<'a, T, U: ?Sized> {
+    // only held to keep writers out, like the guard in the standard
+    // library's `MappedRwLockReadGuard`, which is not stable yet
+    _guard: ReadGuard<'a, T>,
+    value: NonNull<U>,
+    marker: PhantomData<&'a U>,
 }
 
-impl<T> AtomicCache<T> {
-    /// Constructs a new AtomicCache using a boxed closure that lazily evaluates
-    /// to the value that will be cached.
+impl<'a, T, U: ?Sized> MappedAtomicCacheRef<'a, T, U> {
+    fn new(guard: ReadGuard<'a, T>, value: NonNull<U>) -> Self {
+        MappedAtomicCacheRef {
+            _guard: guard,
+            value,
+            marker: PhantomData,
+        }
+    }
+
+    /// narrows the reference further. See [`AtomicCacheRef::map`].
     /// ```
-    /// # use cache::AtomicCache;
-    /// let cache = AtomicCache::new(Box::new(|| 55));
+    /// # use cache::{AtomicCache, AtomicCacheRef, MappedAtomicCacheRef};
+    /// let cache = AtomicCache::new(Box::new(|| (1, (2, 3))));
     ///
-    /// assert_eq!(*cache.get(), 55);
+    /// let pair = AtomicCacheRef::map(cache.get(), |value| &value.1);
+    /// let last = MappedAtomicCacheRef::map(pair, |pair| &pair.1);
+    /// assert_eq!(*last, 3);
     /// ```
-    pub fn new(calc: Box<Fn() -> T + Send + Sync>) -> Self {
-        AtomicCache {
-            calc,
-            data: RwLock::new(None),
-        }
+    ///
+    /// [`AtomicCacheRef::map`]: ./struct.AtomicCacheRef.html#method.map
+    pub fn map<V: ?Sized, F>(this: Self, f: F) -> MappedAtomicCacheRef<'a, T, V>
+    where
+        F: FnOnce(&U) -> &V,
+    {
+        let value = NonNull::from(f(&this));
+        MappedAtomicCacheRef::new(this._guard, value)
     }
 
-    /// gets a reference to the cached value, computing it first if it
-    /// does not exist
+    /// narrows the reference further to an optional part. See
+    /// [`AtomicCacheRef::filter_map`].
     /// ```
-    /// # use cache::AtomicCache;
-    /// let cache = AtomicCache::new(Box::new(|| 55));
+    /// # use cache::{AtomicCache, AtomicCacheRef, MappedAtomicCacheRef};
+    /// let cache = AtomicCache::new(Box::new(|| (1, vec![2, 3])));
     ///
-    /// assert_eq!(*cache.get(), 55);
+    /// let list = AtomicCacheRef::map(cache.get(), |value| &value.1);
+    /// let last = MappedAtomicCacheRef::filter_map(list, |list| list.last());
+    /// assert_eq!(*last.ok().unwrap(), 3);
     /// ```
-    pub fn get(&self) -> AtomicCacheRef<T> {
-        if self.data.read().unwrap().is_none() {
-            let calc = &self.calc;
-            let data = calc();
-
-            let mut write = self.data.write().unwrap();
-            *write = Some(data);
+    ///
+    /// [`AtomicCacheRef::filter_map`]: ./struct.AtomicCacheRef.html#method.filter_map
+    pub fn filter_map<V: ?Sized, F>(
+        this: Self,
+        f: F,
+    ) -> Result<MappedAtomicCacheRef<'a, T, V>, Self>
+    where
+        F: FnOnce(&U) -> Option<&V>,
+    {
+        match f(&this).map(NonNull::from) {
+            Some(value) => Ok(MappedAtomicCacheRef::new(this._guard, value)),
+            None => Err(this),
         }
+    }
+}
 
-        AtomicCacheRef::new(self.data.read().unwrap())
+impl<'a, T, U: ?Sized> Deref for MappedAtomicCacheRef<'a, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `value` was borrowed from the value behind the guard, which
+        // lives in the cache rather than in the guard itself, so it stays put
+        // and unchanged for as long as the guard keeps writers out
+        unsafe { self.value.as_ref() }
     }
 }
 
-/// A thread-safe reference to the cached value stored in an [`AtomicCache`].
-/// Constructed using the [`get`] method on an [`AtomicCache`].
-/// Consists of a thin wrapper around a [`RwLockReadGuard`].
+/// A thread-safe mutable reference to the cached value stored in an
+/// [`AtomicCache`]. Constructed using the [`get_mut`] method on an
+/// [`AtomicCache`]. Holds the cache's lock, with every reader kept out,
+/// until it is dropped.
 ///
 /// [`AtomicCache`]: ./struct.AtomicCache.html
-/// [`get`]: ./struct.AtomicCache.html#method.get
-/// [`RwLockReadGuard`]: https://doc.rust-lang.org/std/sync/struct.RwLockReadGuard.html
-pub struct AtomicCacheRef<'a, T>(RwLockReadGuard<'a, Option<T>>);
+/// [`get_mut`]: ./struct.AtomicCache.html#method.get_mut
+pub struct AtomicCacheRefMut<'a, T>(WriteGuard<'a, T>);
 
-impl<'a, T> AtomicCacheRef<'a, T> {
-    fn new(r: RwLockReadGuard<'a, Option<T>>) -> Self {
-        AtomicCacheRef(r)
+impl<'a, T> AtomicCacheRefMut<'a, T> {
+    fn new(r: WriteGuard<'a, T>) -> Self {
+        AtomicCacheRefMut(r)
     }
 }
 
-impl<'a, T> Deref for AtomicCacheRef<'a, T> {
+impl<'a, T> Deref for AtomicCacheRefMut<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref().unwrap()
+        &self.0.data().as_ref().unwrap().value
+    }
+}
+
+impl<'a, T> DerefMut for AtomicCacheRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0.data_mut().as_mut().unwrap().value
     }
 }
 
+/// what an [`AtomicCache`] or a [`TryAtomicCache`] does when a thread
+/// panicked while running its closure, leaving the lock around the value
+/// poisoned
+///
+/// [`AtomicCache`]: ./struct.AtomicCache.html
+/// [`TryAtomicCache`]: ./struct.TryAtomicCache.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoisonPolicy {
+    /// panic in every later call to `get` or `try_get`
+    #[default]
+    Panic,
+    /// discard whatever value the cache held and run the closure again
+    Recover,
+    /// return [`GetError::Poisoned`], or [`TryGetError::Poisoned`], from
+    /// `try_get` until the cache is invalidated or given a new value; `get`
+    /// panics
+    ///
+    /// [`GetError::Poisoned`]: ./enum.GetError.html#variant.Poisoned
+    /// [`TryGetError::Poisoned`]: ./enum.TryGetError.html#variant.Poisoned
+    Error,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
     use std::thread;
 
     #[derive(Debug, PartialEq)]
@@ -175,4 +1309,412 @@ mod tests {
             });
         }
     }
+
+    #[test]
+    fn test_atomic_single_flight() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(10));
+
+        let counter = Arc::clone(&calls);
+        let cache = Arc::new(AtomicCache::new(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(std::time::Duration::from_millis(50));
+            A::new(7)
+        })));
+
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let barrier = Arc::clone(&barrier);
+
+                thread::spawn(move || {
+                    barrier.wait();
+                    assert_eq!(cache.get().inner(), 7);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "CacheRef to its value is alive")]
+    fn test_invalidate_with_live_ref() {
+        let cache = Cache::new(Box::new(|| A::new(0)));
+        let _value = cache.get();
+
+        cache.invalidate();
+    }
+
+    #[test]
+    fn test_atomic_invalidate_waits_for_readers() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        let cache = Arc::new(AtomicCache::new(Box::new(move || {
+            A::new(counter.fetch_add(1, Ordering::SeqCst))
+        })));
+
+        let value = cache.get();
+        let invalidator = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.invalidate())
+        };
+
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(value.inner(), 0);
+        drop(value);
+
+        invalidator.join().unwrap();
+        assert_eq!(cache.get().inner(), 1);
+    }
+
+    #[test]
+    fn test_atomic_set_drops_replaced_values() {
+        let tracker = Arc::new(());
+        let cache = {
+            let tracker = Arc::clone(&tracker);
+            Arc::new(AtomicCache::new(Box::new(move || Arc::clone(&tracker))))
+        };
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let tracker = Arc::clone(&tracker);
+
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        cache.get();
+                        cache.set(Arc::clone(&tracker));
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // the tracker itself, the closure's copy and the current value
+        assert_eq!(Arc::strong_count(&tracker), 3);
+    }
+
+    #[test]
+    fn test_atomic_mapped_ref_holds_read_lock() {
+        let cache = Arc::new(AtomicCache::new(Box::new(|| (A::new(1), vec![2, 3]))));
+
+        let list = AtomicCacheRef::map(cache.get(), |value| &value.1);
+        let last = MappedAtomicCacheRef::filter_map(list, |list| list.last())
+            .ok()
+            .unwrap();
+        let setter = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.set((A::new(4), vec![5])))
+        };
+
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(*last, 3);
+        drop(last);
+
+        setter.join().unwrap();
+        let first = AtomicCacheRef::map(cache.get(), |value| &value.0);
+        assert_eq!(first.inner(), 4);
+    }
+
+    #[test]
+    fn test_atomic_filter_map_projects_once() {
+        let cache = AtomicCache::new(Box::new(|| vec![1, 2, 3]));
+        let calls = std::cell::Cell::new(0);
+
+        let last = AtomicCacheRef::filter_map(cache.get(), |list| {
+            calls.set(calls.get() + 1);
+            list.last()
+        })
+        .ok()
+        .unwrap();
+        let last = MappedAtomicCacheRef::filter_map(last, |last| {
+            calls.set(calls.get() + 1);
+            Some(last)
+        })
+        .ok()
+        .unwrap();
+
+        assert_eq!((*last, *last), (3, 3));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_atomic_get_arc_outlives_refresh() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        let cache = Arc::new(AtomicCache::new(Box::new(move || {
+            Arc::new(A::new(counter.fetch_add(1, Ordering::SeqCst)))
+        })));
+
+        let old = cache.get_arc();
+        let refresher = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                cache.invalidate();
+                cache.refresh().inner()
+            })
+        };
+
+        assert_eq!(refresher.join().unwrap(), 1);
+        assert_eq!(old.inner(), 0);
+        assert_eq!(cache.get_arc().inner(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // the cache lets go of the values it replaced
+        assert_eq!(Arc::strong_count(&old), 1);
+
+        let current = cache.get_arc();
+        cache.refresh();
+        assert_eq!(Arc::strong_count(&current), 1);
+    }
+
+    #[test]
+    fn test_atomic_ttl_expires() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        let cache = Arc::new(AtomicCache::with_ttl(
+            Box::new(move || A::new(counter.fetch_add(1, Ordering::SeqCst))),
+            std::time::Duration::from_millis(20),
+        ));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || assert_eq!(cache.get().inner(), 0))
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        thread::sleep(std::time::Duration::from_millis(40));
+        assert_eq!(cache.get().inner(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_atomic_ttl_drops_expired_values() {
+        let tracker = Arc::new(());
+        let cache = {
+            let tracker = Arc::clone(&tracker);
+            Arc::new(AtomicCache::with_ttl(
+                Box::new(move || Arc::clone(&tracker)),
+                std::time::Duration::from_millis(5),
+            ))
+        };
+
+        for _ in 0..5 {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || drop(cache.get())).join().unwrap();
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        // the tracker itself, the closure's copy and the current value
+        assert_eq!(Arc::strong_count(&tracker), 3);
+    }
+
+    #[test]
+    fn test_cycle_through_two_caches() {
+        use std::cell::{OnceCell, RefCell};
+        use std::rc::Rc;
+
+        type Shared = Rc<OnceCell<(Cache<usize>, Cache<usize>)>>;
+
+        let caches: Shared = Rc::new(OnceCell::new());
+        let error = Rc::new(RefCell::new(None));
+
+        let (to_b, to_a) = (Rc::downgrade(&caches), Rc::downgrade(&caches));
+        let found = Rc::clone(&error);
+        let a: Box<dyn Fn() -> usize> = Box::new(move || {
+            let caches = to_b.upgrade().unwrap();
+            let b = caches.get().unwrap().1.get();
+
+            *b + 1
+        });
+        let b: Box<dyn Fn() -> usize> = Box::new(move || {
+            let caches = to_a.upgrade().unwrap();
+            let a = caches.get().unwrap().0.try_get();
+
+            a.map(|a| *a).unwrap_or_else(|err| {
+                *found.borrow_mut() = Some(err);
+                0
+            })
+        });
+
+        let (a, b) = caches.get_or_init(|| (Cache::new(a).named("a"), Cache::new(b).named("b")));
+
+        assert_eq!(*a.get(), 1);
+        assert_eq!(*b.get(), 0);
+
+        let error = error.borrow_mut().take().unwrap();
+        assert_eq!(error.caches(), ["a", "b", "a"]);
+        assert_eq!(
+            error.to_string(),
+            "cycle detected while initializing caches: a -> b -> a"
+        );
+    }
+
+    #[test]
+    fn test_atomic_cycle_across_threads() {
+        use std::sync::{Mutex, OnceLock};
+
+        type Calc = Box<dyn Fn() -> usize + Send + Sync>;
+        type Shared = Arc<OnceLock<(AtomicCache<usize>, AtomicCache<usize>)>>;
+
+        let caches: Shared = Arc::new(OnceLock::new());
+        let barrier = Arc::new(Barrier::new(2));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+
+        // each closure waits until both caches are being computed and then
+        // asks for the other one, so the two threads end up waiting on each
+        // other
+        let calc = |other: usize| -> Calc {
+            let caches = Arc::downgrade(&caches);
+            let barrier = Arc::clone(&barrier);
+            let errors = Arc::clone(&errors);
+
+            Box::new(move || {
+                barrier.wait();
+
+                let caches = caches.upgrade().unwrap();
+                let (a, b) = caches.get().unwrap();
+                let other = if other == 0 { a } else { b };
+
+                let value = match other.try_get() {
+                    Ok(value) => *value + 1,
+                    Err(err) => {
+                        errors.lock().unwrap().push(err);
+                        0
+                    }
+                };
+
+                value
+            })
+        };
+
+        let (a, b) = (calc(1), calc(0));
+        caches.get_or_init(|| {
+            (
+                AtomicCache::new(a).named("a"),
+                AtomicCache::new(b).named("b"),
+            )
+        });
+
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let caches = Arc::clone(&caches);
+
+                thread::spawn(move || {
+                    let (a, b) = caches.get().unwrap();
+                    let cache = if i == 0 { a } else { b };
+
+                    *cache.get()
+                })
+            })
+            .collect();
+
+        let mut values: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        values.sort();
+        assert_eq!(values, [0, 1]);
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+
+        let caches = match errors[0] {
+            GetError::Cycle(ref err) => err.caches(),
+            ref err => panic!("unexpected error: {}", err),
+        };
+        assert!(caches == ["a", "b", "a"] || caches == ["b", "a", "b"]);
+    }
+
+    /// builds a cache whose closure panics the first time it is run
+    fn panics_once(policy: PoisonPolicy) -> Arc<AtomicCache<A>> {
+        let calls = AtomicUsize::new(0);
+
+        Arc::new(
+            AtomicCache::new(Box::new(move || {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("calc failed");
+                }
+
+                A::new(1)
+            }) as Box<dyn Fn() -> A + Send + Sync>)
+            .on_poison(policy),
+        )
+    }
+
+    fn poison(cache: &Arc<AtomicCache<A>>) {
+        let cache = Arc::clone(cache);
+
+        assert!(thread::spawn(move || cache.get().inner()).join().is_err());
+    }
+
+    #[test]
+    fn test_poison_recover() {
+        let cache = panics_once(PoisonPolicy::Recover);
+        poison(&cache);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || cache.get().inner())
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_poison_error() {
+        let cache = panics_once(PoisonPolicy::Error);
+        poison(&cache);
+
+        let other = Arc::clone(&cache);
+        let result = thread::spawn(move || other.try_get().map(|v| v.inner()).err());
+        assert_eq!(result.join().unwrap(), Some(GetError::Poisoned));
+
+        cache.invalidate();
+        assert_eq!(cache.try_get().unwrap().inner(), 1);
+    }
+
+    #[test]
+    fn test_poison_panic() {
+        let cache = panics_once(PoisonPolicy::Panic);
+        poison(&cache);
+        poison(&cache);
+    }
+
+    #[test]
+    fn test_atomic_get_mut_from_many_threads() {
+        let cache = Arc::new(AtomicCache::new(Box::new(Vec::new)));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || cache.get_mut().push(i))
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut values = cache.get().clone();
+        values.sort();
+        assert_eq!(values, (0..8).collect::<Vec<_>>());
+    }
 }
@@ -0,0 +1,180 @@
+//! Hooks for observing what a cache does with its values.
+
+use std::ops::Deref;
+use std::time::Duration;
+
+/// receives events from a cache, for logging, metrics or cleaning up after
+/// removed values. Every method does nothing by default, so a listener only
+/// implements the events it cares about.
+///
+/// Caches holding a single value report events for the key `()`. A
+/// thread-safe cache may call its listener while holding a lock, so the
+/// listener must not call back into the cache it is listening to.
+/// ```
+/// # use cache::{CacheListener, CacheMap, RemovalCause};
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// struct Log(Rc<RefCell<Vec<String>>>);
+///
+/// impl CacheListener<u64, u64> for Log {
+///     fn on_removal(&self, key: &u64, value: &u64, cause: RemovalCause) {
+///         self.0.borrow_mut().push(format!("{} -> {} {:?}", key, value, cause));
+///     }
+/// }
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+/// let squares = CacheMap::with_capacity(calc, 1).listened_by(Log(Rc::clone(&log)));
+///
+/// squares.get(&2);
+/// squares.get(&3);
+/// squares.invalidate(&3);
+///
+/// assert_eq!(*log.borrow(), ["2 -> 4 Evicted", "3 -> 9 Invalidated"]);
+/// ```
+pub trait CacheListener<K, V> {
+    /// the closure is about to run for `key`
+    fn on_compute_start(&self, _key: &K) {}
+
+    /// the closure computed `value` for `key`, taking `elapsed`
+    fn on_compute_finish(&self, _key: &K, _value: &V, _elapsed: Duration) {}
+
+    /// a lookup of `key` found `value` without running the closure
+    fn on_hit(&self, _key: &K, _value: &V) {}
+
+    /// the cache dropped `value`, which was stored for `key`. Values handed
+    /// out as shared references may still be alive elsewhere.
+    fn on_removal(&self, _key: &K, _value: &V, _cause: RemovalCause) {}
+}
+
+/// why a cache dropped a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    /// the cache, or the key, was invalidated or the value was taken out
+    Invalidated,
+    /// a new value was stored in its place
+    Replaced,
+    /// the value outlived its time-to-live
+    Expired,
+    /// the value was evicted to keep the cache within its capacity
+    Evicted,
+}
+
+/// the listener of a non-thread-safe cache
+pub(crate) type Listener<K, V> = Option<Box<dyn CacheListener<K, V>>>;
+
+/// the listener of a thread-safe cache
+pub(crate) type SharedListener<K, V> = Option<Box<dyn CacheListener<K, V> + Send + Sync>>;
+
+/// tells `listener` about the values that a keyed cache dropped
+pub(crate) fn report_removals<K, V, R, L>(listener: Option<&L>, removals: Vec<(K, R, RemovalCause)>)
+where
+    R: Deref<Target = V>,
+    L: CacheListener<K, V> + ?Sized,
+{
+    if let Some(listener) = listener {
+        for (key, value, cause) in removals {
+            listener.on_removal(&key, &value, cause);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheListener, RemovalCause};
+    use crate::{Cache, CacheMap};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Log(Rc<RefCell<Vec<String>>>);
+
+    impl CacheListener<u64, u64> for Log {
+        fn on_compute_start(&self, key: &u64) {
+            self.0.borrow_mut().push(format!("start {}", key));
+        }
+
+        fn on_compute_finish(&self, key: &u64, value: &u64, _: Duration) {
+            self.0
+                .borrow_mut()
+                .push(format!("finish {} {}", key, value));
+        }
+
+        fn on_hit(&self, key: &u64, value: &u64) {
+            self.0.borrow_mut().push(format!("hit {} {}", key, value));
+        }
+
+        fn on_removal(&self, key: &u64, value: &u64, cause: RemovalCause) {
+            self.0
+                .borrow_mut()
+                .push(format!("{:?} {} {}", cause, key, value));
+        }
+    }
+
+    impl CacheListener<(), u64> for Log {
+        fn on_compute_finish(&self, _: &(), value: &u64, _: Duration) {
+            self.0.borrow_mut().push(format!("finish {}", value));
+        }
+
+        fn on_hit(&self, _: &(), value: &u64) {
+            self.0.borrow_mut().push(format!("hit {}", value));
+        }
+
+        fn on_removal(&self, _: &(), value: &u64, cause: RemovalCause) {
+            self.0.borrow_mut().push(format!("{:?} {}", cause, value));
+        }
+    }
+
+    #[test]
+    fn test_expired_value_is_reported() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let cache = Cache::with_ttl(Box::new(|| 55), Duration::from_millis(5))
+            .listened_by(Log(Rc::clone(&log)));
+
+        cache.get();
+        cache.get();
+        thread::sleep(Duration::from_millis(10));
+        cache.get();
+
+        assert_eq!(
+            *log.borrow(),
+            ["finish 55", "hit 55", "finish 55", "Expired 55"]
+        );
+    }
+
+    #[test]
+    fn test_map_reports_every_removal() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+        let squares = CacheMap::with_capacity(calc, 2).listened_by(Log(Rc::clone(&log)));
+
+        squares.get(&1);
+        squares.get(&2);
+        squares.get(&1);
+        squares.get(&3);
+        squares.set(3, 0);
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "start 1",
+                "finish 1 1",
+                "start 2",
+                "finish 2 4",
+                "hit 1 1",
+                "start 3",
+                "finish 3 9",
+                "Evicted 2 4",
+                "Replaced 3 9",
+            ]
+        );
+
+        log.borrow_mut().clear();
+        squares.invalidate_all();
+
+        let mut removals = log.borrow().clone();
+        removals.sort();
+        assert_eq!(removals, ["Invalidated 1 1", "Invalidated 3 0"]);
+    }
+}
@@ -0,0 +1,1530 @@
+//! Caches that memoize a closure taking a key, storing one value per key.
+
+use crate::cycle::{CycleError, Initializing, Label, Owner, Waiting};
+use crate::listener::{report_removals, CacheListener, Listener, RemovalCause, SharedListener};
+use crate::policy::{EvictionPolicy, LruPolicy};
+use crate::slot::label;
+use crate::stats::CacheStats;
+use crate::store::{State, Store};
+use crate::trace::Span;
+use crate::weigher::{UnitWeigher, Weigher};
+use std::cell::RefCell;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+/// the keys of a [`CacheMap`]. A loading key is identified by the address of
+/// its `Rc` for the purpose of cycle detection.
+///
+/// [`CacheMap`]: ./struct.CacheMap.html
+type LocalData<K, V, P> = Store<K, Rc<V>, Rc<()>, P>;
+
+/// the keys of an [`AtomicCacheMap`]
+///
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+pub(crate) type SharedData<K, V, P> = Store<K, Arc<V>, Arc<Flight<V>>, P>;
+
+/// a non-thread-safe keyed variant of [`Cache`]. The closure takes a key
+/// and its value is computed and stored separately for every key that is
+/// asked for.
+///
+/// Values are handed out as [`CacheMapRef`]s, which share ownership of the
+/// value instead of borrowing the map. Holding on to one therefore never
+/// gets in the way of computing, invalidating or replacing other values.
+///
+/// [`Cache`]: ./struct.Cache.html
+/// [`CacheMapRef`]: ./struct.CacheMapRef.html
+pub struct CacheMap<K, V, F = Box<dyn Fn(&K) -> V>, P = LruPolicy<K>, W = UnitWeigher> {
+    calc: F,
+    data: RefCell<LocalData<K, V, P>>,
+    weigher: W,
+    name: Option<Arc<str>>,
+    listener: Listener<K, V>,
+}
+
+impl<K, V, F> CacheMap<K, V, F>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+{
+    /// Constructs a new CacheMap using a closure that lazily evaluates to
+    /// the value that will be cached for a key.
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    pub fn new(calc: F) -> Self {
+        CacheMap {
+            calc,
+            data: RefCell::new(Store::new(None, LruPolicy::new())),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+
+    /// Constructs a new CacheMap that holds on to at most `capacity`
+    /// values, evicting the least recently used one whenever a new value
+    /// would take it over capacity. Looking up and storing a value are both
+    /// constant time operations.
+    ///
+    /// Evicted values stay alive for as long as a [`CacheMapRef`] to them
+    /// does.
+    /// ```
+    /// # use cache::CacheMap;
+    /// let calls = std::cell::Cell::new(0);
+    /// let squares = CacheMap::with_capacity(
+    ///     |n: &u64| {
+    ///         calls.set(calls.get() + 1);
+    ///         n * n
+    ///     },
+    ///     2,
+    /// );
+    ///
+    /// squares.get(&1);
+    /// squares.get(&2);
+    /// squares.get(&1);
+    /// squares.get(&3);
+    /// assert_eq!(squares.len(), 2);
+    ///
+    /// // 2 was the least recently used value when 3 was added
+    /// squares.get(&1);
+    /// squares.get(&2);
+    /// assert_eq!(calls.get(), 4);
+    /// ```
+    ///
+    /// [`CacheMapRef`]: ./struct.CacheMapRef.html
+    pub fn with_capacity(calc: F, capacity: usize) -> Self {
+        CacheMap {
+            calc,
+            data: RefCell::new(Store::new(Some(capacity), LruPolicy::new())),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+}
+
+impl<K, V, F, P> CacheMap<K, V, F, P>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+{
+    /// Constructs a new CacheMap that holds on to at most `capacity`
+    /// values, using `policy` to decide which value to evict whenever a new
+    /// value would take it over capacity. See [`EvictionPolicy`] for the
+    /// policies that are available.
+    /// ```
+    /// # use cache::{CacheMap, LfuPolicy};
+    /// let squares = CacheMap::with_policy(Box::new(|n: &u64| n * n), 100, LfuPolicy::new());
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`EvictionPolicy`]: ./trait.EvictionPolicy.html
+    pub fn with_policy(calc: F, capacity: usize, policy: P) -> Self {
+        CacheMap {
+            calc,
+            data: RefCell::new(Store::new(Some(capacity), policy)),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+}
+
+impl<K, V, F, P, W> CacheMap<K, V, F, P, W>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+    W: Weigher<K, V>,
+{
+    /// gets a reference to the value cached for `key`, computing it first
+    /// if it does not exist
+    ///
+    /// The closure may call `get` on the same map for other keys, which
+    /// makes recursive memoization straightforward.
+    /// ```
+    /// # use cache::CacheMap;
+    /// # use std::cell::OnceCell;
+    /// # use std::rc::Rc;
+    /// let slot = Rc::new(OnceCell::<CacheMap<u64, u64>>::new());
+    /// let weak = Rc::downgrade(&slot);
+    /// let fib = slot.get_or_init(|| {
+    ///     CacheMap::new(Box::new(move |&n: &u64| {
+    ///         let slot = weak.upgrade().unwrap();
+    ///         let fib = slot.get().unwrap();
+    ///
+    ///         if n < 2 {
+    ///             n
+    ///         } else {
+    ///             *fib.get(&(n - 1)) + *fib.get(&(n - 2))
+    ///         }
+    ///     }))
+    /// });
+    ///
+    /// assert_eq!(*fib.get(&80), 23_416_728_348_467_685);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the cycle if the closure, directly or
+    /// through other caches, asks for the key it is computing. Use
+    /// [`try_get`] to handle that case as an error instead.
+    ///
+    /// [`try_get`]: #method.try_get
+    pub fn get(&self, key: &K) -> CacheMapRef<V> {
+        match self.try_get(key) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// gets a reference to the value cached for `key` like [`get`], but
+    /// returns a [`CycleError`] instead of panicking if the closure ends up
+    /// asking for the key it is computing
+    /// ```
+    /// # use cache::CacheMap;
+    /// let lengths = CacheMap::new(Box::new(|s: &String| s.len()));
+    ///
+    /// assert_eq!(*lengths.try_get(&"cache".to_string()).unwrap(), 5);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn try_get(&self, key: &K) -> Result<CacheMapRef<V>, CycleError> {
+        let mut data = self.data.borrow_mut();
+        let loading = match data.get(key) {
+            Some(State::Ready(value)) => {
+                let value = Rc::clone(value);
+                drop(data);
+
+                if let Some(ref listener) = self.listener {
+                    listener.on_hit(key, &value);
+                }
+
+                return Ok(CacheMapRef(value));
+            }
+            Some(State::Loading(load)) => Some(Rc::as_ptr(load) as usize),
+            None => None,
+        };
+        drop(data);
+
+        if let Some(id) = loading {
+            let _initializing = Initializing::enter(id, self.label())?;
+            unreachable!("a key is only loading while its closure is running on this thread");
+        }
+
+        let load = Rc::new(());
+        let _initializing = Initializing::enter(Rc::as_ptr(&load) as usize, self.label())?;
+        let _span = Span::init(|| self.describe());
+        self.data.borrow_mut().load(key.clone(), Rc::clone(&load));
+
+        if let Some(ref listener) = self.listener {
+            listener.on_compute_start(key);
+        }
+
+        let guard = Load {
+            data: &self.data,
+            key,
+            load,
+            started: Instant::now(),
+        };
+        let value = (self.calc)(key);
+        let weight = self.weigher.weigh(key, &value);
+
+        if let Some(ref listener) = self.listener {
+            listener.on_compute_finish(key, &value, guard.started.elapsed());
+        }
+
+        let value = Rc::new(value);
+        let removals = guard.finish(Rc::clone(&value), weight);
+        report_removals(self.listener.as_deref(), removals);
+
+        Ok(CacheMapRef(value))
+    }
+
+    /// gives the map a name to use in error messages, such as the
+    /// description of a [`CycleError`]
+    /// ```
+    /// # use cache::CacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+    /// let squares = CacheMap::new(calc).named("squares");
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(Arc::from(name.into()));
+        self
+    }
+
+    /// puts a TinyLFU admission filter in front of the eviction policy, to
+    /// keep a scan of keys that are only asked for once from flushing out
+    /// the popular ones
+    ///
+    /// The filter estimates how often every key has been asked for lately,
+    /// with counters that are halved every so often so that old popularity
+    /// fades. Once the map is full, a new value is only stored if its key
+    /// has been asked for more often than the key the policy would evict to
+    /// make room for it. A value that is turned away is still handed to the
+    /// caller as a [`CacheMapRef`]; it is just not kept.
+    /// ```
+    /// # use cache::CacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+    /// let squares = CacheMap::with_capacity(calc, 2).tiny_lfu();
+    ///
+    /// for _ in 0..3 {
+    ///     squares.get(&1);
+    ///     squares.get(&2);
+    /// }
+    ///
+    /// for n in 100..120 {
+    ///     assert_eq!(*squares.get(&n), n * n);
+    /// }
+    ///
+    /// assert!(squares.contains_key(&1) && squares.contains_key(&2));
+    /// ```
+    ///
+    /// [`CacheMapRef`]: ./struct.CacheMapRef.html
+    pub fn tiny_lfu(mut self) -> Self {
+        self.data.get_mut().enable_admission();
+        self
+    }
+
+    /// starts recording how often the map is hit and how long the closure
+    /// takes, which [`stats`] then reports. Values evicted to make room
+    /// for others count as evictions.
+    /// ```
+    /// # use cache::CacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+    /// let squares = CacheMap::new(calc).record_stats();
+    ///
+    /// squares.get(&7);
+    /// squares.get(&7);
+    /// squares.invalidate(&7);
+    ///
+    /// let stats = squares.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// assert_eq!(stats.invalidations, 1);
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.data.get_mut().enable_stats();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the map
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert_eq!(squares.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.data.borrow().stats()
+    }
+
+    /// hands every event of the map to `listener`: the closure starting and
+    /// finishing for a key, hits, and values being replaced, invalidated or
+    /// evicted
+    /// ```
+    /// # use cache::{CacheListener, CacheMap};
+    /// # use std::cell::Cell;
+    /// # use std::rc::Rc;
+    /// struct Hits(Rc<Cell<u32>>);
+    ///
+    /// impl CacheListener<u64, u64> for Hits {
+    ///     fn on_hit(&self, _: &u64, _: &u64) {
+    ///         self.0.set(self.0.get() + 1);
+    ///     }
+    /// }
+    ///
+    /// let hits = Rc::new(Cell::new(0));
+    /// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+    /// let squares = CacheMap::new(calc).listened_by(Hits(Rc::clone(&hits)));
+    ///
+    /// squares.get(&7);
+    /// squares.get(&7);
+    /// assert_eq!(hits.get(), 1);
+    /// ```
+    pub fn listened_by(mut self, listener: impl CacheListener<K, V> + 'static) -> Self {
+        self.data.get_mut().track_removals();
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// measures values with `weigher`, turning the capacity of the map into
+    /// a budget for the total weight of its values. Once a new value takes
+    /// the map over budget, values are evicted until it fits; a value that
+    /// weighs more than the whole budget is handed to the caller as a
+    /// [`CacheMapRef`] but never stored.
+    ///
+    /// Each value is weighed once, when it is stored. See [`Weigher`] for
+    /// the weighers that are available.
+    /// ```
+    /// # use cache::{ByteWeigher, CacheMap};
+    /// let calc: Box<dyn Fn(&usize) -> Vec<u8>> = Box::new(|n| vec![0; *n]);
+    /// let buffers = CacheMap::with_capacity(calc, 100).weighed_by(ByteWeigher);
+    ///
+    /// buffers.get(&40);
+    /// buffers.get(&50);
+    /// buffers.get(&30);
+    ///
+    /// // 40 was evicted to make room for 30
+    /// assert_eq!(buffers.len(), 2);
+    /// assert_eq!(buffers.weight(), 80);
+    /// ```
+    ///
+    /// [`CacheMapRef`]: ./struct.CacheMapRef.html
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn weighed_by<X: Weigher<K, V>>(self, weigher: X) -> CacheMap<K, V, F, P, X> {
+        CacheMap {
+            calc: self.calc,
+            data: self.data,
+            weigher,
+            name: self.name,
+            listener: self.listener,
+        }
+    }
+
+    /// drops the value cached for `key`, if any, so that the next call to
+    /// [`get`] for that key recomputes it. Outstanding [`CacheMapRef`]s keep
+    /// the old value alive.
+    /// ```
+    /// # use cache::CacheMap;
+    /// # use std::cell::Cell;
+    /// let calls = Cell::new(0);
+    /// let squares = CacheMap::new(|n: &u64| {
+    ///     calls.set(calls.get() + 1);
+    ///     n * n
+    /// });
+    ///
+    /// squares.get(&7);
+    /// squares.invalidate(&7);
+    /// squares.get(&7);
+    /// assert_eq!(calls.get(), 2);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CacheMapRef`]: ./struct.CacheMapRef.html
+    pub fn invalidate(&self, key: &K) {
+        self.update(|data| data.invalidate(key));
+    }
+
+    /// drops every cached value
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// squares.invalidate_all();
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    pub fn invalidate_all(&self) {
+        self.update(|data| data.clear());
+    }
+
+    /// replaces the value cached for `key` with `value`, without running
+    /// the closure
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.set(7, 0);
+    /// assert_eq!(*squares.get(&7), 0);
+    /// ```
+    pub fn set(&self, key: K, value: V) {
+        let weight = self.weigher.weigh(&key, &value);
+        self.update(|data| data.insert(key, Rc::new(value), weight));
+    }
+
+    /// whether a value is currently stored for `key`. Unlike [`get`], this
+    /// does not count as a use of the value.
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert!(squares.contains_key(&7));
+    /// assert!(!squares.contains_key(&8));
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.data.borrow().contains_key(key)
+    }
+
+    /// the number of values currently stored in the map
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert_eq!(squares.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.data.borrow().len()
+    }
+
+    /// whether the map currently stores no values
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert!(squares.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the total weight of the values currently stored in the map, which is
+    /// the number of values unless the map is [`weighed_by`] a [`Weigher`]
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert_eq!(squares.weight(), 1);
+    /// ```
+    ///
+    /// [`weighed_by`]: #method.weighed_by
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn weight(&self) -> usize {
+        self.data.borrow().weight()
+    }
+
+    /// the maximum total weight of the values the map holds on to, or
+    /// `None` if it is unbounded. Unless the map is [`weighed_by`] a
+    /// [`Weigher`], this is the maximum number of values.
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::with_capacity(Box::new(|n: &u64| n * n), 100);
+    ///
+    /// assert_eq!(squares.capacity(), Some(100));
+    /// ```
+    ///
+    /// [`weighed_by`]: #method.weighed_by
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn capacity(&self) -> Option<usize> {
+        self.data.borrow().capacity()
+    }
+
+    /// changes the maximum total weight of the values the map holds on to,
+    /// evicting values straight away if it now holds too much
+    /// ```
+    /// # use cache::CacheMap;
+    /// let squares = CacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// for n in 0..10 {
+    ///     squares.get(&n);
+    /// }
+    ///
+    /// squares.set_capacity(3);
+    /// assert_eq!(squares.len(), 3);
+    /// ```
+    pub fn set_capacity(&self, capacity: usize) {
+        self.update(|data| data.set_capacity(Some(capacity)));
+    }
+
+    /// changes the keys with `change`, then tells the listener about the
+    /// values that were dropped
+    fn update<R>(&self, change: impl FnOnce(&mut LocalData<K, V, P>) -> R) -> R {
+        let (result, removals) = {
+            let mut data = self.data.borrow_mut();
+            let result = change(&mut data);
+            (result, data.take_removals())
+        };
+        report_removals(self.listener.as_deref(), removals);
+
+        result
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.label().describe()
+    }
+
+    fn label(&self) -> Label {
+        label::<V, _>(&self.name, self)
+    }
+}
+
+/// a key of a [`CacheMap`] whose closure is running. Stores the value once
+/// it has been computed, or forgets about the key if the closure panics.
+///
+/// [`CacheMap`]: ./struct.CacheMap.html
+struct Load<'a, K: Eq + Hash + Clone, V, P: EvictionPolicy<K>> {
+    data: &'a RefCell<LocalData<K, V, P>>,
+    key: &'a K,
+    load: Rc<()>,
+    started: Instant,
+}
+
+impl<'a, K: Eq + Hash + Clone, V, P: EvictionPolicy<K>> Load<'a, K, V, P> {
+    /// stores the value, returning whatever that made the map drop
+    fn finish(self, value: Rc<V>, weight: usize) -> Vec<(K, Rc<V>, RemovalCause)> {
+        let mut data = self.data.borrow_mut();
+        data.record_load(self.started.elapsed());
+
+        if self.is_ours(&data) {
+            data.insert(self.key.clone(), value, weight);
+        }
+
+        data.take_removals()
+    }
+
+    /// whether the key is still loading with this load, as opposed to
+    /// having been invalidated or replaced while the closure was running
+    fn is_ours(&self, data: &LocalData<K, V, P>) -> bool {
+        data.loading(self.key)
+            .is_some_and(|load| Rc::ptr_eq(load, &self.load))
+    }
+}
+
+impl<'a, K: Eq + Hash + Clone, V, P: EvictionPolicy<K>> Drop for Load<'a, K, V, P> {
+    fn drop(&mut self) {
+        let mut data = self.data.borrow_mut();
+
+        // only still ours if the closure panicked, in which case there is
+        // no value to report as removed
+        if self.is_ours(&data) {
+            data.remove(self.key, RemovalCause::Invalidated);
+        }
+    }
+}
+
+/// A non-thread-safe reference to a value stored in a [`CacheMap`].
+/// Constructed using the [`get`] method on a [`CacheMap`]. Consists of a thin
+/// wrapper around an [`Rc`], so it can outlive the value being invalidated.
+///
+/// [`CacheMap`]: ./struct.CacheMap.html
+/// [`get`]: ./struct.CacheMap.html#method.get
+/// [`Rc`]: https://doc.rust-lang.org/std/rc/struct.Rc.html
+#[derive(Clone)]
+pub struct CacheMapRef<V>(Rc<V>);
+
+impl<V> Deref for CacheMapRef<V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// a thread-safe variant of [`CacheMap`]
+///
+/// Every key is computed exactly once: if several threads ask for the same
+/// missing key at the same time, the first one runs the closure while the
+/// others block until the value has been published. Threads asking for
+/// different keys never wait on each other's closures, since the map is
+/// only locked long enough to look a key up or store its value. For maps
+/// that many threads use at once, a [`ShardedCacheMap`] spreads the keys
+/// over several locks.
+///
+/// [`CacheMap`]: ./struct.CacheMap.html
+/// [`ShardedCacheMap`]: ./struct.ShardedCacheMap.html
+pub struct AtomicCacheMap<
+    K,
+    V,
+    F = Box<dyn Fn(&K) -> V + Send + Sync>,
+    P = LruPolicy<K>,
+    W = UnitWeigher,
+> {
+    calc: F,
+    data: Mutex<SharedData<K, V, P>>,
+    weigher: W,
+    name: Option<Arc<str>>,
+    listener: SharedListener<K, V>,
+}
+
+/// the closure running for one key of an [`AtomicCacheMap`], which threads
+/// asking for the same key wait on
+///
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+pub(crate) struct Flight<V> {
+    outcome: Mutex<Outcome<V>>,
+    done: Condvar,
+    owner: Owner,
+}
+
+enum Outcome<V> {
+    Running,
+    Done(Arc<V>),
+    Panicked,
+}
+
+impl<V> Flight<V> {
+    fn new() -> Self {
+        Flight {
+            outcome: Mutex::new(Outcome::Running),
+            done: Condvar::new(),
+            owner: Owner::default(),
+        }
+    }
+
+    fn id(self: &Arc<Self>) -> usize {
+        Arc::as_ptr(self) as usize
+    }
+
+    fn complete(&self, outcome: Outcome<V>) {
+        *self.outcome.lock().unwrap_or_else(PoisonError::into_inner) = outcome;
+        self.done.notify_all();
+    }
+
+    /// blocks until the closure has finished, returning `None` if it
+    /// panicked
+    fn wait(&self) -> Option<Arc<V>> {
+        let mut outcome = self.outcome.lock().unwrap_or_else(PoisonError::into_inner);
+
+        loop {
+            match *outcome {
+                Outcome::Running => {}
+                Outcome::Done(ref value) => return Some(Arc::clone(value)),
+                Outcome::Panicked => return None,
+            }
+
+            outcome = self
+                .done
+                .wait(outcome)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl<K, V, F> AtomicCacheMap<K, V, F>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+{
+    /// Constructs a new AtomicCacheMap using a closure that lazily evaluates
+    /// to the value that will be cached for a key.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    pub fn new(calc: F) -> Self {
+        AtomicCacheMap {
+            calc,
+            data: Mutex::new(Store::new(None, LruPolicy::new())),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+
+    /// Constructs a new AtomicCacheMap that holds on to at most `capacity`
+    /// values, evicting the least recently used one whenever a new value
+    /// would take it over capacity. Looking up and storing a value are both
+    /// constant time operations.
+    ///
+    /// Evicted values stay alive for as long as an [`AtomicCacheMapRef`] to them
+    /// does.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// let calls = AtomicUsize::new(0);
+    /// let squares = AtomicCacheMap::with_capacity(
+    ///     |n: &u64| {
+    ///         calls.fetch_add(1, Ordering::SeqCst);
+    ///         n * n
+    ///     },
+    ///     2,
+    /// );
+    ///
+    /// squares.get(&1);
+    /// squares.get(&2);
+    /// squares.get(&1);
+    /// squares.get(&3);
+    /// assert_eq!(squares.len(), 2);
+    ///
+    /// // 2 was the least recently used value when 3 was added
+    /// squares.get(&1);
+    /// squares.get(&2);
+    /// assert_eq!(calls.load(Ordering::SeqCst), 4);
+    /// ```
+    ///
+    /// [`AtomicCacheMapRef`]: ./struct.AtomicCacheMapRef.html
+    pub fn with_capacity(calc: F, capacity: usize) -> Self {
+        AtomicCacheMap {
+            calc,
+            data: Mutex::new(Store::new(Some(capacity), LruPolicy::new())),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+}
+
+impl<K, V, F, P> AtomicCacheMap<K, V, F, P>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+{
+    /// Constructs a new AtomicCacheMap that holds on to at most `capacity`
+    /// values, using `policy` to decide which value to evict whenever a new
+    /// value would take it over capacity. See [`EvictionPolicy`] for the
+    /// policies that are available.
+    /// ```
+    /// # use cache::{AtomicCacheMap, LfuPolicy};
+    /// let squares = AtomicCacheMap::with_policy(Box::new(|n: &u64| n * n), 100, LfuPolicy::new());
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`EvictionPolicy`]: ./trait.EvictionPolicy.html
+    pub fn with_policy(calc: F, capacity: usize, policy: P) -> Self {
+        AtomicCacheMap {
+            calc,
+            data: Mutex::new(Store::new(Some(capacity), policy)),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+}
+
+impl<K, V, F, P, W> AtomicCacheMap<K, V, F, P, W>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+    W: Weigher<K, V>,
+{
+    /// gets a reference to the value cached for `key`, computing it first
+    /// if it does not exist
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// std::thread::scope(|s| {
+    ///     s.spawn(|| assert_eq!(*squares.get(&7), 49));
+    ///     s.spawn(|| assert_eq!(*squares.get(&8), 64));
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the cycle if the closure, directly or
+    /// through other caches, asks for the key it is computing, or if waiting
+    /// for another thread's computation would deadlock because that thread
+    /// is itself waiting on a key the current thread is computing. Use
+    /// [`try_get`] to handle those cases as an error instead.
+    ///
+    /// [`try_get`]: #method.try_get
+    pub fn get(&self, key: &K) -> AtomicCacheMapRef<V> {
+        match self.try_get(key) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// gets a reference to the value cached for `key` like [`get`], but
+    /// returns a [`CycleError`] instead of panicking or deadlocking if the
+    /// keys being computed end up depending on each other
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let lengths = AtomicCacheMap::new(Box::new(|s: &String| s.len()));
+    ///
+    /// assert_eq!(*lengths.try_get(&"cache".to_string()).unwrap(), 5);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn try_get(&self, key: &K) -> Result<AtomicCacheMapRef<V>, CycleError> {
+        try_get_shared(
+            &self.data,
+            self.listener.as_deref(),
+            key,
+            |key| {
+                let value = (self.calc)(key);
+                let weight = self.weigher.weigh(key, &value);
+                (value, weight)
+            },
+            || self.label(),
+        )
+    }
+
+    /// gives the map a name to use in error messages, such as the
+    /// description of a [`CycleError`]
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = AtomicCacheMap::new(calc).named("squares");
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(Arc::from(name.into()));
+        self
+    }
+
+    /// puts a TinyLFU admission filter in front of the eviction policy, to
+    /// keep a scan of keys that are only asked for once from flushing out
+    /// the popular ones
+    ///
+    /// The filter estimates how often every key has been asked for lately,
+    /// with counters that are halved every so often so that old popularity
+    /// fades. Once the map is full, a new value is only stored if its key
+    /// has been asked for more often than the key the policy would evict to
+    /// make room for it. A value that is turned away is still handed to the
+    /// caller as a [`AtomicCacheMapRef`]; it is just not kept.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = AtomicCacheMap::with_capacity(calc, 2).tiny_lfu();
+    ///
+    /// for _ in 0..3 {
+    ///     squares.get(&1);
+    ///     squares.get(&2);
+    /// }
+    ///
+    /// for n in 100..120 {
+    ///     assert_eq!(*squares.get(&n), n * n);
+    /// }
+    ///
+    /// assert!(squares.contains_key(&1) && squares.contains_key(&2));
+    /// ```
+    ///
+    /// [`AtomicCacheMapRef`]: ./struct.AtomicCacheMapRef.html
+    pub fn tiny_lfu(mut self) -> Self {
+        self.data
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .enable_admission();
+        self
+    }
+
+    /// starts recording how often the map is hit and how long the closure
+    /// takes, which [`stats`] then reports. The counters are kept next to
+    /// the values and only updated while the map is locked anyway, so
+    /// recording costs no extra synchronization.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = AtomicCacheMap::new(calc).record_stats();
+    ///
+    /// squares.get(&7);
+    /// squares.get(&7);
+    /// squares.invalidate(&7);
+    ///
+    /// let stats = squares.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// assert_eq!(stats.invalidations, 1);
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.data
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .enable_stats();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the map
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert_eq!(squares.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.lock().stats()
+    }
+
+    /// hands every event of the map to `listener`, like
+    /// [`CacheMap::listened_by`]. The listener is never called while the
+    /// map is locked, so it may use the map itself.
+    /// ```
+    /// # use cache::{AtomicCacheMap, CacheListener, RemovalCause};
+    /// # use std::sync::Mutex;
+    /// # use std::sync::Arc;
+    /// struct Evictions(Arc<Mutex<Vec<u64>>>);
+    ///
+    /// impl CacheListener<u64, u64> for Evictions {
+    ///     fn on_removal(&self, key: &u64, _: &u64, cause: RemovalCause) {
+    ///         if cause == RemovalCause::Evicted {
+    ///             self.0.lock().unwrap().push(*key);
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let evicted = Arc::new(Mutex::new(Vec::new()));
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares =
+    ///     AtomicCacheMap::with_capacity(calc, 2).listened_by(Evictions(Arc::clone(&evicted)));
+    ///
+    /// for n in 0..4 {
+    ///     squares.get(&n);
+    /// }
+    ///
+    /// assert_eq!(*evicted.lock().unwrap(), [0, 1]);
+    /// ```
+    ///
+    /// [`CacheMap::listened_by`]: ./struct.CacheMap.html#method.listened_by
+    pub fn listened_by(
+        mut self,
+        listener: impl CacheListener<K, V> + Send + Sync + 'static,
+    ) -> Self {
+        self.data
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .track_removals();
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// measures values with `weigher`, turning the capacity of the map into
+    /// a budget for the total weight of its values. Once a new value takes
+    /// the map over budget, values are evicted until it fits; a value that
+    /// weighs more than the whole budget is handed to the caller as a
+    /// [`AtomicCacheMapRef`] but never stored.
+    ///
+    /// Each value is weighed once, when it is stored. See [`Weigher`] for
+    /// the weighers that are available.
+    /// ```
+    /// # use cache::{ByteWeigher, AtomicCacheMap};
+    /// let calc: Box<dyn Fn(&usize) -> Vec<u8> + Send + Sync> = Box::new(|n| vec![0; *n]);
+    /// let buffers = AtomicCacheMap::with_capacity(calc, 100).weighed_by(ByteWeigher);
+    ///
+    /// buffers.get(&40);
+    /// buffers.get(&50);
+    /// buffers.get(&30);
+    ///
+    /// // 40 was evicted to make room for 30
+    /// assert_eq!(buffers.len(), 2);
+    /// assert_eq!(buffers.weight(), 80);
+    /// ```
+    ///
+    /// [`AtomicCacheMapRef`]: ./struct.AtomicCacheMapRef.html
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn weighed_by<X: Weigher<K, V>>(self, weigher: X) -> AtomicCacheMap<K, V, F, P, X> {
+        AtomicCacheMap {
+            calc: self.calc,
+            data: self.data,
+            weigher,
+            name: self.name,
+            listener: self.listener,
+        }
+    }
+
+    /// drops the value cached for `key`, if any, so that the next call to
+    /// [`get`] for that key recomputes it. Outstanding
+    /// [`AtomicCacheMapRef`]s keep the old value alive, so this never waits
+    /// on readers.
+    ///
+    /// If the closure is running for `key`, the threads already waiting on
+    /// it still get its value, but the value is not stored.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// let calls = AtomicUsize::new(0);
+    /// let squares = AtomicCacheMap::new(|n: &u64| {
+    ///     calls.fetch_add(1, Ordering::SeqCst);
+    ///     n * n
+    /// });
+    ///
+    /// let old = squares.get(&7);
+    /// squares.invalidate(&7);
+    /// squares.get(&7);
+    ///
+    /// assert_eq!(*old, 49);
+    /// assert_eq!(calls.load(Ordering::SeqCst), 2);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`AtomicCacheMapRef`]: ./struct.AtomicCacheMapRef.html
+    pub fn invalidate(&self, key: &K) {
+        self.update(|data| data.invalidate(key));
+    }
+
+    /// drops every cached value
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// squares.invalidate_all();
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    pub fn invalidate_all(&self) {
+        self.update(|data| data.clear());
+    }
+
+    /// replaces the value cached for `key` with `value`, without running
+    /// the closure
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.set(7, 0);
+    /// assert_eq!(*squares.get(&7), 0);
+    /// ```
+    pub fn set(&self, key: K, value: V) {
+        let weight = self.weigher.weigh(&key, &value);
+        self.update(|data| data.insert(key, Arc::new(value), weight));
+    }
+
+    /// whether a value is currently stored for `key`. Unlike [`get`], this
+    /// does not count as a use of the value.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert!(squares.contains_key(&7));
+    /// assert!(!squares.contains_key(&8));
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.lock().contains_key(key)
+    }
+
+    /// the number of values currently stored in the map
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert_eq!(squares.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// whether the map currently stores no values
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert!(squares.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the total weight of the values currently stored in the map, which is
+    /// the number of values unless the map is [`weighed_by`] a [`Weigher`]
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert_eq!(squares.weight(), 1);
+    /// ```
+    ///
+    /// [`weighed_by`]: #method.weighed_by
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn weight(&self) -> usize {
+        self.lock().weight()
+    }
+
+    /// the maximum total weight of the values the map holds on to, or
+    /// `None` if it is unbounded. Unless the map is [`weighed_by`] a
+    /// [`Weigher`], this is the maximum number of values.
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::with_capacity(Box::new(|n: &u64| n * n), 100);
+    ///
+    /// assert_eq!(squares.capacity(), Some(100));
+    /// ```
+    ///
+    /// [`weighed_by`]: #method.weighed_by
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn capacity(&self) -> Option<usize> {
+        self.lock().capacity()
+    }
+
+    /// changes the maximum total weight of the values the map holds on to,
+    /// evicting values straight away if it now holds too much
+    /// ```
+    /// # use cache::AtomicCacheMap;
+    /// let squares = AtomicCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// for n in 0..10 {
+    ///     squares.get(&n);
+    /// }
+    ///
+    /// squares.set_capacity(3);
+    /// assert_eq!(squares.len(), 3);
+    /// ```
+    pub fn set_capacity(&self, capacity: usize) {
+        self.update(|data| data.set_capacity(Some(capacity)));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SharedData<K, V, P>> {
+        lock(&self.data)
+    }
+
+    fn update<R>(&self, change: impl FnOnce(&mut SharedData<K, V, P>) -> R) -> R {
+        update_shared(&self.data, self.listener.as_deref(), change)
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.label().describe()
+    }
+
+    fn label(&self) -> Label {
+        label::<V, _>(&self.name, self)
+    }
+}
+
+pub(crate) fn lock<T>(data: &Mutex<T>) -> MutexGuard<'_, T> {
+    // the closure never runs while the lock is held, so the only code that
+    // could panic with it is `Hash` and `Eq` on the keys or the eviction
+    // policy, neither of which can leave the map itself in a broken state
+    data.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// changes the keys in `store` with `change`, then tells `listener` about
+/// the values that were dropped once the lock has been released
+pub(crate) fn update_shared<K, V, P, R>(
+    store: &Mutex<SharedData<K, V, P>>,
+    listener: Option<&(dyn CacheListener<K, V> + Send + Sync)>,
+    change: impl FnOnce(&mut SharedData<K, V, P>) -> R,
+) -> R
+where
+    K: Eq + Hash + Clone,
+    P: EvictionPolicy<K>,
+{
+    let (result, removals) = {
+        let mut data = lock(store);
+        let result = change(&mut data);
+        (result, data.take_removals())
+    };
+    report_removals(listener, removals);
+
+    result
+}
+
+/// gets the value stored for `key` in `store`, running `compute` for it
+/// first if it is missing and no other thread is computing it already.
+/// `compute` returns the value along with its weight.
+pub(crate) fn try_get_shared<K, V, P>(
+    store: &Mutex<SharedData<K, V, P>>,
+    listener: Option<&(dyn CacheListener<K, V> + Send + Sync)>,
+    key: &K,
+    compute: impl FnOnce(&K) -> (V, usize),
+    label: impl Fn() -> Label,
+) -> Result<AtomicCacheMapRef<V>, CycleError>
+where
+    K: Eq + Hash + Clone,
+    P: EvictionPolicy<K>,
+{
+    loop {
+        let mut data = lock(store);
+
+        let flight = match data.get(key) {
+            Some(State::Ready(value)) => {
+                let value = Arc::clone(value);
+                drop(data);
+
+                if let Some(listener) = listener {
+                    listener.on_hit(key, &value);
+                }
+
+                return Ok(AtomicCacheMapRef(value));
+            }
+            Some(State::Loading(flight)) => Arc::clone(flight),
+            None => {
+                let flight = Arc::new(Flight::new());
+                data.load(key.clone(), Arc::clone(&flight));
+                drop(data);
+
+                let guard = Flying {
+                    data: store,
+                    listener,
+                    key,
+                    flight: Arc::clone(&flight),
+                    started: Instant::now(),
+                };
+
+                // a thread that starts waiting on the key before this one
+                // records itself as the owner cannot see who owns it yet,
+                // but then this thread finds the cycle as soon as its
+                // closure waits on anything
+                let _initializing =
+                    Initializing::enter_shared(flight.id(), label(), &flight.owner)?;
+                let _span = Span::init(|| label().describe());
+
+                if let Some(listener) = listener {
+                    listener.on_compute_start(key);
+                }
+
+                let (value, weight) = compute(key);
+
+                if let Some(listener) = listener {
+                    listener.on_compute_finish(key, &value, guard.started.elapsed());
+                }
+
+                let value = Arc::new(value);
+                guard.finish(Arc::clone(&value), weight);
+
+                return Ok(AtomicCacheMapRef(value));
+            }
+        };
+        drop(data);
+
+        let _waiting = Waiting::enter(flight.id(), label(), &flight.owner)?;
+        let _span = Span::wait(|| label().describe());
+
+        // if the closure panicked, the key has been forgotten and the
+        // next thread around the loop tries again
+        if let Some(value) = flight.wait() {
+            return Ok(AtomicCacheMapRef(value));
+        }
+    }
+}
+
+/// a key of an [`AtomicCacheMap`] whose closure is running on the current
+/// thread. Stores the value and wakes the waiting threads once it has been
+/// computed, or forgets about the key and lets them retry if the closure
+/// panics.
+///
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+struct Flying<'a, K, V, P>
+where
+    K: Eq + Hash + Clone,
+    P: EvictionPolicy<K>,
+{
+    data: &'a Mutex<SharedData<K, V, P>>,
+    listener: Option<&'a (dyn CacheListener<K, V> + Send + Sync)>,
+    key: &'a K,
+    flight: Arc<Flight<V>>,
+    started: Instant,
+}
+
+impl<'a, K, V, P> Flying<'a, K, V, P>
+where
+    K: Eq + Hash + Clone,
+    P: EvictionPolicy<K>,
+{
+    fn finish(self, value: Arc<V>, weight: usize) {
+        self.settle(Some((value, weight)));
+    }
+
+    /// replaces the state of the key if it still belongs to this flight,
+    /// then wakes the waiting threads
+    fn settle(&self, value: Option<(Arc<V>, usize)>) {
+        update_shared(self.data, self.listener, |data| {
+            if value.is_some() {
+                data.record_load(self.started.elapsed());
+            }
+
+            let ours = data
+                .loading(self.key)
+                .is_some_and(|flight| Arc::ptr_eq(flight, &self.flight));
+
+            if ours {
+                match value {
+                    Some((ref value, weight)) => {
+                        data.insert(self.key.clone(), Arc::clone(value), weight);
+                    }
+                    // the key was still loading, so there is no value to
+                    // report as removed
+                    None => data.remove(self.key, RemovalCause::Invalidated),
+                }
+            }
+        });
+
+        self.flight.complete(match value {
+            Some((value, _)) => Outcome::Done(value),
+            None => Outcome::Panicked,
+        });
+    }
+}
+
+impl<'a, K, V, P> Drop for Flying<'a, K, V, P>
+where
+    K: Eq + Hash + Clone,
+    P: EvictionPolicy<K>,
+{
+    fn drop(&mut self) {
+        let outcome = self.flight.outcome.lock();
+        let running = matches!(
+            *outcome.unwrap_or_else(PoisonError::into_inner),
+            Outcome::Running
+        );
+
+        // only still running if the closure panicked or never got to run
+        if running {
+            self.settle(None);
+        }
+    }
+}
+
+/// A thread-safe reference to a value stored in an [`AtomicCacheMap`].
+/// Constructed using the [`get`] method on an [`AtomicCacheMap`]. Consists
+/// of a thin wrapper around an [`Arc`], so unlike an [`AtomicCacheRef`] it
+/// holds no lock and can outlive the value being invalidated.
+///
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+/// [`get`]: ./struct.AtomicCacheMap.html#method.get
+/// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+/// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+#[derive(Clone)]
+pub struct AtomicCacheMapRef<V>(Arc<V>);
+
+impl<V> Deref for AtomicCacheMapRef<V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_atomic_single_flight_per_key() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        let map = Arc::new(AtomicCacheMap::new(Box::new(move |n: &usize| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            n * 2
+        })));
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let map = Arc::clone(&map);
+                thread::spawn(move || *map.get(&(i % 4)))
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), (i % 4) * 2);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_atomic_panicking_key_is_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        let map = Arc::new(AtomicCacheMap::new(Box::new(move |n: &usize| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                thread::sleep(Duration::from_millis(20));
+                panic!("first call fails");
+            }
+
+            *n
+        })));
+
+        let first = {
+            let map = Arc::clone(&map);
+            thread::spawn(move || *map.get(&55))
+        };
+        thread::sleep(Duration::from_millis(5));
+        let second = {
+            let map = Arc::clone(&map);
+            thread::spawn(move || *map.get(&55))
+        };
+
+        assert!(first.join().is_err());
+        assert_eq!(second.join().unwrap(), 55);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_oversized_value_replaces_stored_value() {
+        let calc: Box<dyn Fn(&u64) -> String> = Box::new(|n| n.to_string());
+        let map = CacheMap::with_capacity(calc, 8).weighed_by(|_: &u64, s: &String| s.len());
+
+        map.get(&12345);
+        assert_eq!(map.weight(), 5);
+
+        map.set(12345, "too long to fit".to_string());
+        assert!(!map.contains_key(&12345));
+        assert_eq!(map.weight(), 0);
+        assert_eq!(*map.get(&12345), "12345");
+    }
+
+    #[test]
+    fn test_shrinking_may_evict_the_last_insert() {
+        let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+        let map = CacheMap::with_policy(calc, 2, crate::ClockPolicy::new());
+
+        map.get(&1);
+        map.get(&2);
+        map.get(&1);
+        map.set_capacity(1);
+
+        assert!(map.contains_key(&1) && !map.contains_key(&2));
+    }
+
+    #[test]
+    fn test_atomic_miss_does_not_lock_cycle_graph() {
+        let map = Arc::new(AtomicCacheMap::new(Box::new(|n: &u64| n * n)));
+
+        // only threads about to block on another thread's closure lock the
+        // cycle graph, so a miss nobody else is loading finishes without it
+        let graph = crate::cycle::graph();
+        let getter = {
+            let map = Arc::clone(&map);
+            thread::spawn(move || *map.get(&3))
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !getter.is_finished() && Instant::now() < deadline {
+            thread::yield_now();
+        }
+        let finished = getter.is_finished();
+        drop(graph);
+
+        assert!(finished);
+        assert_eq!(getter.join().unwrap(), 9);
+    }
+
+    #[test]
+    fn test_recursive_key_is_a_cycle() {
+        let slot = Rc::new(std::cell::OnceCell::<CacheMap<u64, String>>::new());
+        let weak = Rc::downgrade(&slot);
+        let map = slot.get_or_init(|| {
+            let calc: Box<dyn Fn(&u64) -> String> = Box::new(move |&n| {
+                let slot = weak.upgrade().unwrap();
+                let map = slot.get().unwrap();
+
+                match map.try_get(&(n % 2)) {
+                    Ok(value) => (*value).clone(),
+                    Err(err) => err.to_string(),
+                }
+            });
+
+            CacheMap::new(calc).named("parity")
+        });
+
+        assert_eq!(
+            *map.get(&3),
+            "cycle detected while initializing caches: parity -> parity"
+        );
+    }
+}
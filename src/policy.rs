@@ -0,0 +1,853 @@
+//! Policies that decide which key a bounded keyed cache evicts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// decides which value a bounded [`CacheMap`] or [`AtomicCacheMap`] evicts
+/// once it holds more values than its capacity allows
+///
+/// The map tells the policy about every key whose value is stored, read or
+/// removed, and asks it for a key to evict whenever it is over capacity.
+/// Keys whose value is still being computed are not tracked.
+///
+/// The crate ships [`LruPolicy`], [`LfuPolicy`], [`FifoPolicy`],
+/// [`ClockPolicy`] and [`ArcPolicy`], but any other policy can be plugged in
+/// by implementing this trait.
+/// ```
+/// # use cache::{CacheMap, EvictionPolicy};
+/// # use std::collections::BTreeSet;
+/// /// evicts the smallest key first
+/// #[derive(Default)]
+/// struct SmallestFirst(BTreeSet<u64>);
+///
+/// impl EvictionPolicy<u64> for SmallestFirst {
+///     fn on_insert(&mut self, key: &u64) {
+///         self.0.insert(*key);
+///     }
+///
+///     fn on_access(&mut self, _: &u64) {}
+///
+///     fn on_remove(&mut self, key: &u64) {
+///         self.0.remove(key);
+///     }
+///
+///     fn evict(&mut self) -> Option<u64> {
+///         self.0.pop_first()
+///     }
+/// }
+///
+/// let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+/// let squares = CacheMap::with_policy(calc, 2, SmallestFirst::default());
+///
+/// for n in 0..5 {
+///     squares.get(&n);
+/// }
+///
+/// assert!(squares.contains_key(&3) && squares.contains_key(&4));
+/// ```
+///
+/// [`CacheMap`]: ./struct.CacheMap.html
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+/// [`LruPolicy`]: ./struct.LruPolicy.html
+/// [`LfuPolicy`]: ./struct.LfuPolicy.html
+/// [`FifoPolicy`]: ./struct.FifoPolicy.html
+/// [`ClockPolicy`]: ./struct.ClockPolicy.html
+/// [`ArcPolicy`]: ./struct.ArcPolicy.html
+pub trait EvictionPolicy<K> {
+    /// the value of `key` has been stored, either for the first time or
+    /// replacing an older value
+    fn on_insert(&mut self, key: &K);
+
+    /// the value of `key` has been read
+    fn on_access(&mut self, key: &K);
+
+    /// the value of `key` has been invalidated, and the policy should
+    /// forget about it
+    fn on_remove(&mut self, key: &K);
+
+    /// chooses a key to evict and forgets about it. Only returns `None` if
+    /// the policy is not tracking any keys.
+    ///
+    /// When a new value takes the map over capacity, this is called right
+    /// after [`on_insert`] for it, until the map calls [`on_settled`].
+    /// Policies should avoid handing back the key that was just inserted in
+    /// between unless it is the only one they track, or the map would never
+    /// take in new values once it is full.
+    ///
+    /// [`on_insert`]: #tymethod.on_insert
+    /// [`on_settled`]: #method.on_settled
+    fn evict(&mut self) -> Option<K>;
+
+    /// the map is done making room for the key last passed to
+    /// [`on_insert`], so later calls to [`evict`] may hand it back like any
+    /// other key
+    ///
+    /// [`on_insert`]: #tymethod.on_insert
+    /// [`evict`]: #tymethod.evict
+    fn on_settled(&mut self) {}
+
+    /// the map has decided to keep the key last returned by [`evict`]
+    /// after all, because the admission filter turned away the new value
+    /// instead. By default the key is tracked again as if it had just been
+    /// inserted.
+    ///
+    /// [`evict`]: #tymethod.evict
+    fn restore(&mut self, key: &K) {
+        self.on_insert(key);
+    }
+
+    /// the capacity of the map has been set or changed. Policies that size
+    /// their own bookkeeping after the capacity can override this.
+    fn on_capacity(&mut self, capacity: Option<usize>) {
+        let _ = capacity;
+    }
+}
+
+impl<K, P> EvictionPolicy<K> for Box<P>
+where
+    P: EvictionPolicy<K> + ?Sized,
+{
+    fn on_insert(&mut self, key: &K) {
+        (**self).on_insert(key);
+    }
+
+    fn on_access(&mut self, key: &K) {
+        (**self).on_access(key);
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        (**self).on_remove(key);
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        (**self).evict()
+    }
+
+    fn on_settled(&mut self) {
+        (**self).on_settled();
+    }
+
+    fn restore(&mut self, key: &K) {
+        (**self).restore(key);
+    }
+
+    fn on_capacity(&mut self, capacity: Option<usize>) {
+        (**self).on_capacity(capacity);
+    }
+}
+
+/// a list of keys in the order in which they were pushed, supporting
+/// constant time removal of any key
+///
+/// The keys form a doubly linked list, stored as indices into a vector so
+/// that no node needs to own or borrow another. Slots of removed nodes are
+/// reused by later insertions.
+struct List<K> {
+    index: HashMap<K, usize>,
+    nodes: Vec<Node<K>>,
+    free: Vec<usize>,
+    /// the most recently pushed key
+    head: Option<usize>,
+    /// the least recently pushed key
+    tail: Option<usize>,
+}
+
+struct Node<K> {
+    key: K,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl<K> List<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn new() -> Self {
+        List {
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// adds `key` to the front of the list, moving it there if it is
+    /// already present
+    fn push(&mut self, key: &K) {
+        if let Some(&i) = self.index.get(key) {
+            self.unlink(i);
+            self.link(i);
+            return;
+        }
+
+        let node = Node {
+            key: key.clone(),
+            prev: None,
+            next: None,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        self.index.insert(key.clone(), i);
+        self.link(i);
+    }
+
+    /// moves `key` to the front of the list, if it is present
+    fn touch(&mut self, key: &K) {
+        if self.contains(key) {
+            self.push(key);
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> bool {
+        match self.index.remove(key) {
+            Some(i) => {
+                self.unlink(i);
+                self.free.push(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// removes and returns the key at the back of the list
+    fn pop(&mut self) -> Option<K> {
+        let i = self.tail?;
+        let key = self.nodes[i].key.clone();
+        self.remove(&key);
+
+        Some(key)
+    }
+
+    fn link(&mut self, i: usize) {
+        self.nodes[i].prev = None;
+        self.nodes[i].next = self.head;
+
+        match self.head {
+            Some(head) => self.nodes[head].prev = Some(i),
+            None => self.tail = Some(i),
+        }
+
+        self.head = Some(i);
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => self.head = next,
+        }
+
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+}
+
+/// evicts the least recently used value. Every operation takes constant
+/// time.
+///
+/// This is the policy used by [`CacheMap::with_capacity`] and
+/// [`AtomicCacheMap::with_capacity`].
+/// ```
+/// # use cache::{CacheMap, LruPolicy};
+/// let squares = CacheMap::with_policy(Box::new(|n: &u64| n * n), 2, LruPolicy::new());
+///
+/// squares.get(&1);
+/// squares.get(&2);
+/// squares.get(&1);
+/// squares.get(&3);
+/// assert!(squares.contains_key(&1) && !squares.contains_key(&2));
+/// ```
+///
+/// [`CacheMap::with_capacity`]: ./struct.CacheMap.html#method.with_capacity
+/// [`AtomicCacheMap::with_capacity`]: ./struct.AtomicCacheMap.html#method.with_capacity
+pub struct LruPolicy<K>(List<K>);
+
+impl<K> LruPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Constructs a new LruPolicy that is not tracking any keys.
+    pub fn new() -> Self {
+        LruPolicy(List::new())
+    }
+}
+
+impl<K> Default for LruPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        LruPolicy::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for LruPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.0.push(key);
+    }
+
+    fn on_access(&mut self, key: &K) {
+        self.0.touch(key);
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        self.0.remove(key);
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        self.0.pop()
+    }
+}
+
+/// evicts the value that was stored first, regardless of how often it has
+/// been read since. Every operation takes constant time.
+/// ```
+/// # use cache::{CacheMap, FifoPolicy};
+/// let squares = CacheMap::with_policy(Box::new(|n: &u64| n * n), 2, FifoPolicy::new());
+///
+/// squares.get(&1);
+/// squares.get(&2);
+/// squares.get(&1);
+/// squares.get(&3);
+/// assert!(squares.contains_key(&2) && !squares.contains_key(&1));
+/// ```
+pub struct FifoPolicy<K>(List<K>);
+
+impl<K> FifoPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Constructs a new FifoPolicy that is not tracking any keys.
+    pub fn new() -> Self {
+        FifoPolicy(List::new())
+    }
+}
+
+impl<K> Default for FifoPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        FifoPolicy::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for FifoPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn on_insert(&mut self, key: &K) {
+        if !self.0.contains(key) {
+            self.0.push(key);
+        }
+    }
+
+    fn on_access(&mut self, _: &K) {}
+
+    fn on_remove(&mut self, key: &K) {
+        self.0.remove(key);
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        self.0.pop()
+    }
+}
+
+/// evicts the least frequently used value, breaking ties in favour of
+/// keeping the more recently used one. Every operation takes logarithmic
+/// time.
+/// ```
+/// # use cache::{CacheMap, LfuPolicy};
+/// let squares = CacheMap::with_policy(Box::new(|n: &u64| n * n), 2, LfuPolicy::new());
+///
+/// squares.get(&1);
+/// squares.get(&1);
+/// squares.get(&2);
+/// squares.get(&2);
+/// squares.get(&2);
+/// squares.get(&3);
+/// assert!(squares.contains_key(&2) && !squares.contains_key(&1));
+/// ```
+pub struct LfuPolicy<K> {
+    /// the use count of every key, along with the tick of its last use
+    uses: HashMap<K, (u64, u64)>,
+    /// every key ordered by use count, then by last use
+    order: BTreeMap<(u64, u64), K>,
+    tick: u64,
+    /// the key stored last, which starts out with the lowest count but
+    /// should not be evicted to make room for itself. Cleared once the map
+    /// has settled.
+    incoming: Option<K>,
+}
+
+impl<K> LfuPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Constructs a new LfuPolicy that is not tracking any keys.
+    pub fn new() -> Self {
+        LfuPolicy {
+            uses: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            incoming: None,
+        }
+    }
+
+    /// records a use of `key`, starting its count at 1 if `insert` is set
+    /// and it is not being tracked yet
+    fn bump(&mut self, key: &K, insert: bool) {
+        self.tick += 1;
+
+        let count = match self.uses.get(key) {
+            Some(&rank) => {
+                self.order.remove(&rank);
+                rank.0 + 1
+            }
+            None if insert => 1,
+            None => return,
+        };
+
+        let rank = (count, self.tick);
+        self.uses.insert(key.clone(), rank);
+        self.order.insert(rank, key.clone());
+    }
+}
+
+impl<K> Default for LfuPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        LfuPolicy::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for LfuPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.bump(key, true);
+        self.incoming = Some(key.clone());
+    }
+
+    fn on_access(&mut self, key: &K) {
+        self.bump(key, false);
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        if let Some(rank) = self.uses.remove(key) {
+            self.order.remove(&rank);
+        }
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        let incoming = self.incoming.as_ref();
+        let mut candidates = self.order.iter();
+        let (&rank, _) = candidates
+            .find(|&(_, key)| Some(key) != incoming)
+            .or_else(|| self.order.iter().next())?;
+
+        let key = self.order.remove(&rank).unwrap();
+        self.uses.remove(&key);
+
+        Some(key)
+    }
+
+    fn on_settled(&mut self) {
+        self.incoming = None;
+    }
+}
+
+/// approximates [`LruPolicy`] with a single reference bit per key, so that
+/// reading a value never reorders anything. Keys sit in a ring that a hand
+/// sweeps over when looking for a victim, clearing the bit of every key it
+/// passes and evicting the first key whose bit was already clear.
+/// ```
+/// # use cache::{CacheMap, ClockPolicy};
+/// let squares = CacheMap::with_policy(Box::new(|n: &u64| n * n), 2, ClockPolicy::new());
+///
+/// squares.get(&1);
+/// squares.get(&2);
+/// squares.get(&1);
+/// squares.get(&3);
+/// assert!(squares.contains_key(&1) && !squares.contains_key(&2));
+/// ```
+///
+/// [`LruPolicy`]: ./struct.LruPolicy.html
+pub struct ClockPolicy<K> {
+    index: HashMap<K, usize>,
+    /// each key along with its reference bit. Slots of removed keys are
+    /// left empty until a later insertion reuses them.
+    ring: Vec<Option<(K, bool)>>,
+    free: Vec<usize>,
+    hand: usize,
+    /// the key stored last, which should not be evicted to make room for
+    /// itself. Cleared once the map has settled.
+    incoming: Option<K>,
+}
+
+impl<K> ClockPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Constructs a new ClockPolicy that is not tracking any keys.
+    pub fn new() -> Self {
+        ClockPolicy {
+            index: HashMap::new(),
+            ring: Vec::new(),
+            free: Vec::new(),
+            hand: 0,
+            incoming: None,
+        }
+    }
+}
+
+impl<K> Default for ClockPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        ClockPolicy::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for ClockPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.incoming = Some(key.clone());
+
+        if self.index.contains_key(key) {
+            self.on_access(key);
+            return;
+        }
+
+        let slot = Some((key.clone(), false));
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.ring[i] = slot;
+                i
+            }
+            None => {
+                self.ring.push(slot);
+                self.ring.len() - 1
+            }
+        };
+
+        self.index.insert(key.clone(), i);
+    }
+
+    fn on_access(&mut self, key: &K) {
+        if let Some(&i) = self.index.get(key) {
+            if let Some((_, ref mut referenced)) = self.ring[i] {
+                *referenced = true;
+            }
+        }
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        if let Some(i) = self.index.remove(key) {
+            self.ring[i] = None;
+            self.free.push(i);
+        }
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        if self.index.is_empty() {
+            return None;
+        }
+
+        // every referenced key passed on the first round has its bit
+        // cleared, so this finds a victim within two rounds, skipping over
+        // the incoming key unless there is nothing else to evict
+        loop {
+            let i = self.hand;
+            self.hand = (self.hand + 1) % self.ring.len();
+
+            let only = self.index.len() == 1;
+
+            match self.ring[i] {
+                Some((ref key, _)) if !only && Some(key) == self.incoming.as_ref() => {}
+                Some((_, ref mut referenced)) if *referenced => *referenced = false,
+                Some(_) => {
+                    let (key, _) = self.ring[i].take().unwrap();
+                    self.index.remove(&key);
+                    self.free.push(i);
+
+                    return Some(key);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn on_settled(&mut self) {
+        self.incoming = None;
+    }
+}
+
+/// the Adaptive Replacement Cache policy, which balances recency against
+/// frequency on its own depending on the workload
+///
+/// Keys that have been used once live in one LRU list and keys that have
+/// been used again in another. The policy also remembers the keys it
+/// recently evicted from each list, and whenever one of those is stored
+/// again it grows the share of the capacity given to the list that should
+/// have kept it. Every operation takes constant time.
+///
+/// The size of the lists of evicted keys follows the capacity of the map.
+/// ```
+/// # use cache::{ArcPolicy, CacheMap};
+/// let squares = CacheMap::with_policy(Box::new(|n: &u64| n * n), 2, ArcPolicy::new());
+///
+/// squares.get(&1);
+/// squares.get(&1);
+/// for n in 2..10 {
+///     squares.get(&n);
+/// }
+///
+/// // 1 was used twice, so a scan of keys used once did not evict it
+/// assert!(squares.contains_key(&1));
+/// ```
+pub struct ArcPolicy<K> {
+    /// stored keys that have been used once
+    recent: List<K>,
+    /// stored keys that have been used more than once
+    frequent: List<K>,
+    /// keys recently evicted from `recent`
+    recent_ghosts: List<K>,
+    /// keys recently evicted from `frequent`
+    frequent_ghosts: List<K>,
+    /// the share of the capacity that `recent` is aiming for
+    target: usize,
+    capacity: usize,
+    /// the key stored last, which should not be evicted to make room for
+    /// itself. Cleared once the map has settled.
+    incoming: Option<K>,
+    /// whether the key stored last was found in `frequent_ghosts`
+    incoming_was_frequent: bool,
+    /// the key evicted last, and whether it came from `recent`
+    evicted: Option<(K, bool)>,
+}
+
+impl<K> ArcPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Constructs a new ArcPolicy that is not tracking any keys.
+    pub fn new() -> Self {
+        ArcPolicy {
+            recent: List::new(),
+            frequent: List::new(),
+            recent_ghosts: List::new(),
+            frequent_ghosts: List::new(),
+            target: 0,
+            capacity: usize::MAX,
+            incoming: None,
+            incoming_was_frequent: false,
+            evicted: None,
+        }
+    }
+
+    /// forgets the oldest evicted keys once they outnumber the capacity
+    fn trim_ghosts(&mut self) {
+        while self.recent.len() + self.recent_ghosts.len() > self.capacity {
+            if self.recent_ghosts.pop().is_none() {
+                break;
+            }
+        }
+
+        let total = |arc: &Self| {
+            arc.recent.len()
+                + arc.frequent.len()
+                + arc.recent_ghosts.len()
+                + arc.frequent_ghosts.len()
+        };
+
+        while total(self) > self.capacity.saturating_mul(2) {
+            if self.frequent_ghosts.pop().is_none() {
+                break;
+            }
+        }
+    }
+}
+
+impl<K> Default for ArcPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        ArcPolicy::new()
+    }
+}
+
+impl<K> EvictionPolicy<K> for ArcPolicy<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.incoming = Some(key.clone());
+        self.incoming_was_frequent = false;
+
+        if self.recent.contains(key) || self.frequent.contains(key) {
+            self.on_access(key);
+            return;
+        }
+
+        if self.recent_ghosts.remove(key) {
+            let step = (self.frequent_ghosts.len() / (self.recent_ghosts.len() + 1)).max(1);
+            self.target = (self.target + step).min(self.capacity);
+            self.frequent.push(key);
+        } else if self.frequent_ghosts.remove(key) {
+            let step = (self.recent_ghosts.len() / (self.frequent_ghosts.len() + 1)).max(1);
+            self.target = self.target.saturating_sub(step);
+            self.incoming_was_frequent = true;
+            self.frequent.push(key);
+        } else {
+            self.recent.push(key);
+        }
+
+        self.trim_ghosts();
+    }
+
+    fn on_access(&mut self, key: &K) {
+        if self.recent.remove(key) {
+            self.frequent.push(key);
+        } else {
+            self.frequent.touch(key);
+        }
+    }
+
+    fn on_remove(&mut self, key: &K) {
+        self.recent.remove(key);
+        self.frequent.remove(key);
+    }
+
+    fn evict(&mut self) -> Option<K> {
+        // the incoming key does not count towards the size of its list, as
+        // in the original algorithm where room is made before it is added
+        let incoming_is_recent = self
+            .incoming
+            .as_ref()
+            .is_some_and(|key| self.recent.contains(key));
+        let recent = self.recent.len() - incoming_is_recent as usize;
+
+        let from_recent = recent > 0
+            && (recent > self.target
+                || (self.incoming_was_frequent && recent == self.target)
+                || self.frequent.len() == 0);
+
+        let key = if from_recent {
+            let key = self.recent.pop()?;
+            self.recent_ghosts.push(&key);
+            key
+        } else {
+            let key = self.frequent.pop().or_else(|| self.recent.pop())?;
+            self.frequent_ghosts.push(&key);
+            key
+        };
+        self.evicted = Some((key.clone(), from_recent));
+
+        self.trim_ghosts();
+
+        Some(key)
+    }
+
+    fn on_settled(&mut self) {
+        self.incoming = None;
+        self.incoming_was_frequent = false;
+    }
+
+    fn restore(&mut self, key: &K) {
+        // put the key back where it was evicted from, without treating it
+        // as a hit on the list of evicted keys
+        match self.evicted.take() {
+            Some((evicted, true)) if evicted == *key => {
+                self.recent_ghosts.remove(key);
+                self.recent.push(key);
+            }
+            Some((evicted, false)) if evicted == *key => {
+                self.frequent_ghosts.remove(key);
+                self.frequent.push(key);
+            }
+            _ => self.on_insert(key),
+        }
+    }
+
+    fn on_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity.unwrap_or(usize::MAX);
+        self.target = self.target.min(self.capacity);
+        self.trim_ghosts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evictions<P: EvictionPolicy<u32>>(mut policy: P) -> Vec<u32> {
+        for key in 0..4 {
+            policy.on_insert(&key);
+        }
+
+        policy.on_access(&0);
+        policy.on_access(&0);
+        policy.on_remove(&2);
+        policy.on_insert(&4);
+
+        std::iter::from_fn(|| policy.evict()).collect()
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        assert_eq!(evictions(LruPolicy::new()), [1, 3, 0, 4]);
+        assert_eq!(evictions(FifoPolicy::new()), [0, 1, 3, 4]);
+        assert_eq!(evictions(LfuPolicy::new()), [1, 3, 0, 4]);
+        assert_eq!(evictions(ClockPolicy::new()), [1, 3, 0, 4]);
+        assert_eq!(evictions(ArcPolicy::new()), [1, 3, 0, 4]);
+    }
+
+    fn first_eviction_after_settling<P: EvictionPolicy<u32>>(mut policy: P) -> Option<u32> {
+        policy.on_insert(&0);
+        policy.on_settled();
+        policy.on_access(&0);
+        policy.on_insert(&1);
+        policy.on_settled();
+
+        policy.evict()
+    }
+
+    #[test]
+    fn test_settled_key_is_not_protected() {
+        assert_eq!(first_eviction_after_settling(LfuPolicy::new()), Some(1));
+        assert_eq!(first_eviction_after_settling(ClockPolicy::new()), Some(1));
+        assert_eq!(first_eviction_after_settling(ArcPolicy::new()), Some(1));
+    }
+}
@@ -0,0 +1,607 @@
+//! A thread-safe keyed cache split into independently locked shards.
+
+use crate::cycle::CycleError;
+use crate::cycle::Label;
+use crate::listener::{CacheListener, SharedListener};
+use crate::map::{lock, try_get_shared, update_shared, AtomicCacheMapRef, SharedData};
+use crate::policy::{EvictionPolicy, LruPolicy};
+use crate::slot::label;
+use crate::stats::CacheStats;
+use crate::store::Store;
+use crate::weigher::{UnitWeigher, Weigher};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// a variant of [`AtomicCacheMap`] for maps that many threads use at once
+///
+/// The keys are spread over a number of shards by their hash, and every
+/// shard has a lock, an eviction policy and a share of the capacity of its
+/// own. Threads only contend when they ask for keys in the same shard, so
+/// throughput keeps up as threads are added. Like an [`AtomicCacheMap`],
+/// every key is computed exactly once, however many threads ask for it.
+/// ```
+/// # use cache::ShardedCacheMap;
+/// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+///
+/// std::thread::scope(|s| {
+///     for n in 0..8 {
+///         let squares = &squares;
+///         s.spawn(move || assert_eq!(*squares.get(&n), n * n));
+///     }
+/// });
+///
+/// assert_eq!(squares.len(), 8);
+/// ```
+///
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+pub struct ShardedCacheMap<
+    K,
+    V,
+    F = Box<dyn Fn(&K) -> V + Send + Sync>,
+    P = LruPolicy<K>,
+    W = UnitWeigher,
+> {
+    calc: F,
+    shards: Box<[Mutex<SharedData<K, V, P>>]>,
+    hasher: RandomState,
+    weigher: W,
+    name: Option<Arc<str>>,
+    listener: SharedListener<K, V>,
+}
+
+impl<K, V, F> ShardedCacheMap<K, V, F>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+{
+    /// Constructs a new ShardedCacheMap using a closure that lazily
+    /// evaluates to the value that will be cached for a key. The map has
+    /// four shards for every thread the machine can run at once.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    pub fn new(calc: F) -> Self {
+        ShardedCacheMap::build(calc, None, default_shards(), LruPolicy::new)
+    }
+
+    /// Constructs a new ShardedCacheMap that holds on to at most `capacity`
+    /// values. Every shard gets an equal share of the capacity and evicts
+    /// its least recently used value whenever a new value would take it
+    /// over its share.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::with_capacity(Box::new(|n: &u64| n * n), 100);
+    ///
+    /// for n in 0..1000 {
+    ///     squares.get(&n);
+    /// }
+    ///
+    /// assert!(squares.len() <= 100);
+    /// ```
+    pub fn with_capacity(calc: F, capacity: usize) -> Self {
+        ShardedCacheMap::build(calc, Some(capacity), default_shards(), LruPolicy::new)
+    }
+}
+
+impl<K, V, F, P> ShardedCacheMap<K, V, F, P>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+{
+    /// Constructs a new ShardedCacheMap that holds on to at most `capacity`
+    /// values, using a policy made by `policy` in every shard to decide
+    /// which value to evict whenever a new value would take the shard over
+    /// its share of the capacity. See [`EvictionPolicy`] for the policies
+    /// that are available.
+    /// ```
+    /// # use cache::{LfuPolicy, ShardedCacheMap};
+    /// let squares = ShardedCacheMap::with_policy(Box::new(|n: &u64| n * n), 100, LfuPolicy::new);
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`EvictionPolicy`]: ./trait.EvictionPolicy.html
+    pub fn with_policy(calc: F, capacity: usize, policy: impl Fn() -> P) -> Self {
+        ShardedCacheMap::build(calc, Some(capacity), default_shards(), policy)
+    }
+
+    /// Constructs a new ShardedCacheMap like [`with_policy`], but with
+    /// `shards` shards instead of a number based on the machine. A map never
+    /// has more shards than its capacity, so that every shard can hold at
+    /// least one value, and always has at least one.
+    /// ```
+    /// # use cache::{LruPolicy, ShardedCacheMap};
+    /// let squares = ShardedCacheMap::with_shards(Box::new(|n: &u64| n * n), 100, 16, LruPolicy::new);
+    ///
+    /// assert_eq!(squares.shard_count(), 16);
+    /// assert_eq!(squares.capacity(), Some(100));
+    /// ```
+    ///
+    /// [`with_policy`]: #method.with_policy
+    pub fn with_shards(calc: F, capacity: usize, shards: usize, policy: impl Fn() -> P) -> Self {
+        ShardedCacheMap::build(calc, Some(capacity), shards, policy)
+    }
+
+    fn build(calc: F, capacity: Option<usize>, shards: usize, policy: impl Fn() -> P) -> Self {
+        let count = match capacity {
+            Some(capacity) => shards.min(capacity),
+            None => shards,
+        }
+        .max(1);
+
+        let shards = (0..count)
+            .map(|i| Mutex::new(Store::new(share(capacity, count, i), policy())))
+            .collect();
+
+        ShardedCacheMap {
+            calc,
+            shards,
+            hasher: RandomState::new(),
+            weigher: UnitWeigher,
+            name: None,
+            listener: None,
+        }
+    }
+}
+
+impl<K, V, F, P, W> ShardedCacheMap<K, V, F, P, W>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&K) -> V,
+    P: EvictionPolicy<K>,
+    W: Weigher<K, V>,
+{
+    /// gets a reference to the value cached for `key`, computing it first
+    /// if it does not exist. Only the shard that `key` belongs to is locked,
+    /// and only while looking the key up or storing its value.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// std::thread::scope(|s| {
+    ///     s.spawn(|| assert_eq!(*squares.get(&7), 49));
+    ///     s.spawn(|| assert_eq!(*squares.get(&8), 64));
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the cycle if the closure, directly or
+    /// through other caches, asks for the key it is computing, or if waiting
+    /// for another thread's computation would deadlock because that thread
+    /// is itself waiting on a key the current thread is computing. Use
+    /// [`try_get`] to handle those cases as an error instead.
+    ///
+    /// [`try_get`]: #method.try_get
+    pub fn get(&self, key: &K) -> AtomicCacheMapRef<V> {
+        match self.try_get(key) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// gets a reference to the value cached for `key` like [`get`], but
+    /// returns a [`CycleError`] instead of panicking or deadlocking if the
+    /// keys being computed end up depending on each other
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let lengths = ShardedCacheMap::new(Box::new(|s: &String| s.len()));
+    ///
+    /// assert_eq!(*lengths.try_get(&"cache".to_string()).unwrap(), 5);
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn try_get(&self, key: &K) -> Result<AtomicCacheMapRef<V>, CycleError> {
+        try_get_shared(
+            self.shard(key),
+            self.listener.as_deref(),
+            key,
+            |key| {
+                let value = (self.calc)(key);
+                let weight = self.weigher.weigh(key, &value);
+                (value, weight)
+            },
+            || self.label(),
+        )
+    }
+
+    /// gives the map a name to use in error messages, such as the
+    /// description of a [`CycleError`]
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = ShardedCacheMap::new(calc).named("squares");
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(Arc::from(name.into()));
+        self
+    }
+
+    /// puts a TinyLFU admission filter in front of the eviction policy of
+    /// every shard, as described for [`AtomicCacheMap::tiny_lfu`]
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = ShardedCacheMap::with_capacity(calc, 100).tiny_lfu();
+    ///
+    /// assert_eq!(*squares.get(&7), 49);
+    /// ```
+    ///
+    /// [`AtomicCacheMap::tiny_lfu`]: ./struct.AtomicCacheMap.html#method.tiny_lfu
+    pub fn tiny_lfu(mut self) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .enable_admission();
+        }
+        self
+    }
+
+    /// starts recording how often the map is hit and how long the closure
+    /// takes, which [`stats`] then reports. Every shard keeps its own
+    /// counters, which are only updated while the shard is locked anyway, and
+    /// the snapshot adds them up.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = ShardedCacheMap::new(calc).record_stats();
+    ///
+    /// squares.get(&7);
+    /// squares.get(&7);
+    /// squares.invalidate(&7);
+    ///
+    /// let stats = squares.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// assert_eq!(stats.invalidations, 1);
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .enable_stats();
+        }
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the map
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert_eq!(squares.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        let mut total = CacheStats::default();
+
+        for shard in self.shards.iter() {
+            total = total + lock(shard).stats()?;
+        }
+
+        Some(total)
+    }
+
+    /// hands every event of the map to `listener`, like
+    /// [`AtomicCacheMap::listened_by`]
+    /// ```
+    /// # use cache::{CacheListener, ShardedCacheMap};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// struct Starts(Arc<AtomicUsize>);
+    ///
+    /// impl CacheListener<u64, u64> for Starts {
+    ///     fn on_compute_start(&self, _: &u64) {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let starts = Arc::new(AtomicUsize::new(0));
+    /// let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+    /// let squares = ShardedCacheMap::new(calc).listened_by(Starts(Arc::clone(&starts)));
+    ///
+    /// std::thread::scope(|s| {
+    ///     for _ in 0..4 {
+    ///         s.spawn(|| squares.get(&7));
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(starts.load(Ordering::Relaxed), 1);
+    /// ```
+    ///
+    /// [`AtomicCacheMap::listened_by`]: ./struct.AtomicCacheMap.html#method.listened_by
+    pub fn listened_by(
+        mut self,
+        listener: impl CacheListener<K, V> + Send + Sync + 'static,
+    ) -> Self {
+        for shard in self.shards.iter_mut() {
+            shard
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .track_removals();
+        }
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// measures values with `weigher`, turning the capacity of every shard
+    /// into a budget for the total weight of its values, as described for
+    /// [`AtomicCacheMap::weighed_by`]. A value that weighs more than the
+    /// share of the shard it belongs to is never stored.
+    /// ```
+    /// # use cache::{ByteWeigher, LruPolicy, ShardedCacheMap};
+    /// let calc: Box<dyn Fn(&usize) -> Vec<u8> + Send + Sync> = Box::new(|n| vec![0; *n]);
+    /// let buffers = ShardedCacheMap::with_shards(calc, 1000, 4, LruPolicy::new)
+    ///     .weighed_by(ByteWeigher);
+    ///
+    /// buffers.get(&100);
+    /// buffers.get(&500);
+    /// assert_eq!(buffers.weight(), 100);
+    /// ```
+    ///
+    /// [`AtomicCacheMap::weighed_by`]: ./struct.AtomicCacheMap.html#method.weighed_by
+    pub fn weighed_by<X: Weigher<K, V>>(self, weigher: X) -> ShardedCacheMap<K, V, F, P, X> {
+        ShardedCacheMap {
+            calc: self.calc,
+            shards: self.shards,
+            hasher: self.hasher,
+            weigher,
+            name: self.name,
+            listener: self.listener,
+        }
+    }
+
+    /// drops the value cached for `key`, if any, so that the next call to
+    /// [`get`] for that key recomputes it. Outstanding
+    /// [`AtomicCacheMapRef`]s keep the old value alive.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// squares.invalidate(&7);
+    /// assert!(!squares.contains_key(&7));
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    /// [`AtomicCacheMapRef`]: ./struct.AtomicCacheMapRef.html
+    pub fn invalidate(&self, key: &K) {
+        self.update(self.shard(key), |data| data.invalidate(key));
+    }
+
+    /// drops every cached value. The shards are cleared one after the
+    /// other, so a value stored by another thread in the meantime may
+    /// survive.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// squares.invalidate_all();
+    /// assert!(squares.is_empty());
+    /// ```
+    pub fn invalidate_all(&self) {
+        for shard in self.shards.iter() {
+            self.update(shard, |data| data.clear());
+        }
+    }
+
+    /// replaces the value cached for `key` with `value`, without running
+    /// the closure
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.set(7, 0);
+    /// assert_eq!(*squares.get(&7), 0);
+    /// ```
+    pub fn set(&self, key: K, value: V) {
+        let weight = self.weigher.weigh(&key, &value);
+        self.update(self.shard(&key), |data| {
+            data.insert(key, Arc::new(value), weight)
+        });
+    }
+
+    /// whether a value is currently stored for `key`. Unlike [`get`], this
+    /// does not count as a use of the value.
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert!(squares.contains_key(&7));
+    /// assert!(!squares.contains_key(&8));
+    /// ```
+    ///
+    /// [`get`]: #method.get
+    pub fn contains_key(&self, key: &K) -> bool {
+        lock(self.shard(key)).contains_key(key)
+    }
+
+    /// the number of values currently stored in the map, added up over the
+    /// shards one after the other
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert_eq!(squares.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).len()).sum()
+    }
+
+    /// whether the map currently stores no values
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// assert!(squares.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the total weight of the values currently stored in the map, which is
+    /// the number of values unless the map is [`weighed_by`] a [`Weigher`]
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::new(Box::new(|n: &u64| n * n));
+    ///
+    /// squares.get(&7);
+    /// assert_eq!(squares.weight(), 1);
+    /// ```
+    ///
+    /// [`weighed_by`]: #method.weighed_by
+    /// [`Weigher`]: ./trait.Weigher.html
+    pub fn weight(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).weight()).sum()
+    }
+
+    /// the maximum total weight of the values the map holds on to, added up
+    /// over the shards, or `None` if it is unbounded
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::with_capacity(Box::new(|n: &u64| n * n), 100);
+    ///
+    /// assert_eq!(squares.capacity(), Some(100));
+    /// ```
+    pub fn capacity(&self) -> Option<usize> {
+        self.shards.iter().map(|shard| lock(shard).capacity()).sum()
+    }
+
+    /// changes the maximum total weight of the values the map holds on to,
+    /// sharing it out between the shards again and evicting values straight
+    /// away from shards that now hold too much. The number of shards stays
+    /// the same.
+    /// ```
+    /// # use cache::{LruPolicy, ShardedCacheMap};
+    /// let squares = ShardedCacheMap::with_shards(Box::new(|n: &u64| n * n), 100, 4, LruPolicy::new);
+    ///
+    /// for n in 0..100 {
+    ///     squares.get(&n);
+    /// }
+    ///
+    /// squares.set_capacity(8);
+    /// assert!(squares.len() <= 8);
+    /// ```
+    pub fn set_capacity(&self, capacity: usize) {
+        let count = self.shards.len();
+
+        for (i, shard) in self.shards.iter().enumerate() {
+            self.update(shard, |data| {
+                data.set_capacity(share(Some(capacity), count, i))
+            });
+        }
+    }
+
+    /// the number of independently locked shards the keys are spread over
+    /// ```
+    /// # use cache::ShardedCacheMap;
+    /// let squares = ShardedCacheMap::with_capacity(Box::new(|n: &u64| n * n), 1);
+    ///
+    /// assert_eq!(squares.shard_count(), 1);
+    /// ```
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn update<R>(
+        &self,
+        shard: &Mutex<SharedData<K, V, P>>,
+        change: impl FnOnce(&mut SharedData<K, V, P>) -> R,
+    ) -> R {
+        update_shared(shard, self.listener.as_deref(), change)
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<SharedData<K, V, P>> {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.label().describe()
+    }
+
+    fn label(&self) -> Label {
+        label::<V, _>(&self.name, self)
+    }
+}
+
+/// four shards for every thread the machine can run at once, which keeps
+/// the odds of two threads wanting the same shard low
+fn default_shards() -> usize {
+    4 * thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// the part of `capacity` that shard `i` out of `count` gets. The remainder
+/// goes to the first shards, so the shares add up to the capacity.
+fn share(capacity: Option<usize>, count: usize, i: usize) -> Option<usize> {
+    capacity.map(|capacity| capacity / count + usize::from(i < capacity % count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_single_flight_per_key() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        let map = Arc::new(ShardedCacheMap::new(Box::new(move |n: &usize| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            n * 2
+        })));
+
+        let handles: Vec<_> = (0..32)
+            .map(|i| {
+                let map = Arc::clone(&map);
+                thread::spawn(move || *map.get(&(i % 8)))
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), (i % 8) * 2);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_capacity_is_shared_out() {
+        assert_eq!(
+            (0..4).map(|i| share(Some(10), 4, i)).collect::<Vec<_>>(),
+            vec![Some(3), Some(3), Some(2), Some(2)]
+        );
+
+        let calc: Box<dyn Fn(&u64) -> u64 + Send + Sync> = Box::new(|n| n * n);
+        let map = ShardedCacheMap::with_shards(calc, 3, 8, LruPolicy::new);
+        assert_eq!(map.shard_count(), 3);
+
+        for n in 0..100 {
+            map.get(&n);
+        }
+
+        assert_eq!(map.len(), 3);
+    }
+}
@@ -0,0 +1,746 @@
+//! Storage shared by every cache type that holds a single lazily computed
+//! value. The public cache types pair one of these slots with a closure.
+
+use crate::cycle::{CycleError, Initializing, Label, Owner, Waiting};
+use crate::listener::{CacheListener, Listener, RemovalCause, SharedListener};
+use crate::stats::{AtomicStats, CacheStats, Stats};
+use crate::trace::Span;
+use crate::{AtomicCacheRef, CacheRef, CacheRefMut, PoisonPolicy};
+use std::cell::{RefCell, RefMut, UnsafeCell};
+use std::convert::Infallible;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+/// a cached value along with the time at which it was computed
+pub(crate) struct Entry<T> {
+    pub(crate) value: T,
+    created: Instant,
+}
+
+impl<T> Entry<T> {
+    fn new(value: T) -> Self {
+        Entry {
+            value,
+            created: Instant::now(),
+        }
+    }
+
+    fn is_fresh(&self, ttl: Option<Duration>) -> bool {
+        ttl.is_none_or(|ttl| self.created.elapsed() < ttl)
+    }
+}
+
+fn is_fresh<T>(data: &Option<Entry<T>>, ttl: Option<Duration>) -> bool {
+    match *data {
+        Some(ref entry) => entry.is_fresh(ttl),
+        None => false,
+    }
+}
+
+/// the ways in which filling a slot can fail
+pub(crate) enum InitError<E> {
+    /// the closure asked for the value of its own cache
+    Cycle(CycleError),
+    /// another thread panicked while holding the lock on the value
+    Poisoned,
+    /// the closure itself failed
+    Failed(E),
+}
+
+/// non-thread-safe storage for a single cached value
+pub(crate) struct Slot<T> {
+    data: RefCell<Option<Entry<T>>>,
+    ttl: Option<Duration>,
+    name: Option<Arc<str>>,
+    stats: Stats,
+    listener: Listener<(), T>,
+}
+
+impl<T> Slot<T> {
+    pub(crate) fn new(ttl: Option<Duration>) -> Self {
+        Slot {
+            data: RefCell::new(None),
+            ttl,
+            name: None,
+            stats: Stats::default(),
+            listener: None,
+        }
+    }
+
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = Some(Arc::from(name));
+    }
+
+    pub(crate) fn enable_stats(&mut self) {
+        self.stats.enable();
+    }
+
+    pub(crate) fn stats(&self) -> Option<CacheStats> {
+        self.stats.snapshot()
+    }
+
+    pub(crate) fn set_listener(&mut self, listener: Box<dyn CacheListener<(), T>>) {
+        self.listener = Some(listener);
+    }
+
+    /// returns the cached value, running `calc` first if there is no value
+    /// or it has expired. Errors from `calc` leave the slot empty.
+    pub(crate) fn get_or_try_init<E, F>(&self, calc: F) -> Result<CacheRef<'_, T>, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        self.fill(calc)?;
+
+        Ok(CacheRef::new(self.data.borrow()))
+    }
+
+    /// like [`get_or_try_init`], but hands out mutable access to the value
+    ///
+    /// [`get_or_try_init`]: #method.get_or_try_init
+    pub(crate) fn get_mut_or_try_init<E, F>(
+        &self,
+        calc: F,
+    ) -> Result<CacheRefMut<'_, T>, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        self.fill(calc)?;
+
+        Ok(CacheRefMut::new(self.borrow_mut()))
+    }
+
+    /// mutable access to the value through a unique reference, which needs
+    /// no runtime borrow checks at all
+    pub(crate) fn force_mut<F>(&mut self, calc: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        if is_fresh(self.data.get_mut(), self.ttl) {
+            self.stats.hit();
+
+            if let Some(ref listener) = self.listener {
+                listener.on_hit(&(), &self.data.get_mut().as_ref().unwrap().value);
+            }
+        } else {
+            self.stats.miss();
+
+            let _span = Span::compute(self.data.get_mut().is_some(), || self.describe());
+            let value = self.compute(calc);
+            let expired = self.data.get_mut().replace(Entry::new(value));
+            self.expire(expired);
+        }
+
+        &mut self.data.get_mut().as_mut().unwrap().value
+    }
+
+    /// the stored value, whether or not it has expired
+    pub(crate) fn into_inner(self) -> Option<T> {
+        self.data.into_inner().map(|entry| entry.value)
+    }
+
+    fn fill<E, F>(&self, calc: F) -> Result<(), InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        let data = self.data.borrow();
+
+        if is_fresh(&data, self.ttl) {
+            self.stats.hit();
+            self.notify(|listener| listener.on_hit(&(), &data.as_ref().unwrap().value));
+        } else {
+            let stale = data.is_some();
+            drop(data);
+            self.stats.miss();
+
+            let _initializing =
+                Initializing::enter(self.id(), self.label()).map_err(InitError::Cycle)?;
+            let _span = Span::compute(stale, || self.describe());
+            let data = self.try_compute(calc).map_err(InitError::Failed)?;
+
+            let expired = self.borrow_mut().replace(Entry::new(data));
+            self.expire(expired);
+        }
+
+        Ok(())
+    }
+
+    /// runs `calc`, recording it as a load and telling the listener
+    fn try_compute<E>(&self, calc: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        self.notify(|listener| listener.on_compute_start(&()));
+
+        let started = Instant::now();
+        let result = calc();
+        let elapsed = started.elapsed();
+        self.stats.load(elapsed);
+
+        if let Ok(ref value) = result {
+            self.notify(|listener| listener.on_compute_finish(&(), value, elapsed));
+        }
+
+        result
+    }
+
+    fn compute(&self, calc: impl FnOnce() -> T) -> T {
+        self.try_compute(|| Ok::<_, Infallible>(calc()))
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// counts a value that was replaced because it expired as evicted, and
+    /// reports it to the listener
+    fn expire(&self, expired: Option<Entry<T>>) {
+        if let Some(entry) = expired {
+            self.stats.evict(1);
+            self.notify(|listener| listener.on_removal(&(), &entry.value, RemovalCause::Expired));
+        }
+    }
+
+    fn notify(&self, event: impl FnOnce(&dyn CacheListener<(), T>)) {
+        if let Some(ref listener) = self.listener {
+            event(&**listener);
+        }
+    }
+
+    pub(crate) fn take(&self) -> Option<T> {
+        let value = self.borrow_mut().take().map(|entry| entry.value);
+
+        if let Some(ref value) = value {
+            self.stats.invalidate(1);
+            self.notify(|listener| listener.on_removal(&(), value, RemovalCause::Invalidated));
+        }
+
+        value
+    }
+
+    pub(crate) fn set(&self, value: T) {
+        let replaced = self.borrow_mut().replace(Entry::new(value));
+
+        if let Some(entry) = replaced {
+            self.notify(|listener| listener.on_removal(&(), &entry.value, RemovalCause::Replaced));
+        }
+    }
+
+    fn borrow_mut(&self) -> RefMut<'_, Option<Entry<T>>> {
+        self.data
+            .try_borrow_mut()
+            .expect("cannot modify a Cache while a CacheRef to its value is alive")
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.label().describe()
+    }
+
+    fn label(&self) -> Label {
+        label::<T, _>(&self.name, self)
+    }
+}
+
+/// the label of a cache holding `T`s, which identifies it by its address if
+/// it was not given a name
+pub(crate) fn label<T, S>(name: &Option<Arc<str>>, cache: &S) -> Label {
+    Label::new::<T>(name, cache as *const S as usize)
+}
+
+/// the most reader stripes an `AtomicSlot` is split into
+const MAX_STRIPES: usize = 16;
+
+/// the number of reader stripes in every `AtomicSlot`: enough for each
+/// core to get its own, up to `MAX_STRIPES`
+fn stripes() -> usize {
+    static STRIPES: LazyLock<usize> = LazyLock::new(|| {
+        let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        cores.next_power_of_two().min(MAX_STRIPES)
+    });
+
+    *STRIPES
+}
+
+thread_local! {
+    /// the stripe the current thread counts itself in on as a reader,
+    /// before it is wrapped around the number of stripes
+    static STRIPE: usize = {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    };
+}
+
+/// a count of readers, padded out to a cache line of its own so that
+/// threads counting themselves in on different stripes do not contend
+#[repr(align(128))]
+#[derive(Default)]
+struct Stripe(AtomicUsize);
+
+/// thread-safe storage for a single cached value
+///
+/// Works like a read-write lock whose read side is spread over several
+/// stripes. A reader counts itself in on its own thread's stripe and then
+/// checks that no writer is active, so readers on different threads never
+/// write to the same cache line. A writer holds `lock`, raises `writing`
+/// and waits for every stripe to drain; readers that find it active back
+/// out and block on `lock` instead.
+pub(crate) struct AtomicSlot<T> {
+    data: UnsafeCell<Option<Entry<T>>>,
+    readers: Box<[Stripe]>,
+    writing: AtomicBool,
+    /// the writer waiting for the readers to leave, if any
+    writer: Mutex<Option<Thread>>,
+    /// held by writers and by the thread running the closure, so that only
+    /// one thread does either at a time
+    lock: Mutex<()>,
+    owner: Owner,
+    ttl: Option<Duration>,
+    name: Option<Arc<str>>,
+    poison: PoisonPolicy,
+    stats: AtomicStats,
+    listener: SharedListener<(), T>,
+}
+
+// SAFETY: the data is only accessed by counted readers, or by a single
+// writer once every reader has left, just like the value in a `RwLock`
+unsafe impl<T: Send + Sync> Sync for AtomicSlot<T> {}
+
+impl<T> AtomicSlot<T> {
+    pub(crate) fn new(ttl: Option<Duration>) -> Self {
+        AtomicSlot {
+            data: UnsafeCell::new(None),
+            readers: (0..stripes()).map(|_| Stripe::default()).collect(),
+            writing: AtomicBool::new(false),
+            writer: Mutex::new(None),
+            lock: Mutex::new(()),
+            owner: Owner::default(),
+            ttl,
+            name: None,
+            poison: PoisonPolicy::default(),
+            stats: AtomicStats::default(),
+            listener: None,
+        }
+    }
+
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = Some(Arc::from(name));
+    }
+
+    pub(crate) fn set_poison_policy(&mut self, poison: PoisonPolicy) {
+        self.poison = poison;
+    }
+
+    pub(crate) fn enable_stats(&mut self) {
+        self.stats.enable();
+    }
+
+    pub(crate) fn stats(&self) -> Option<CacheStats> {
+        self.stats.snapshot()
+    }
+
+    pub(crate) fn set_listener(&mut self, listener: Box<dyn CacheListener<(), T> + Send + Sync>) {
+        self.listener = Some(listener);
+    }
+
+    /// runs `calc`, recording it as a load and telling the listener. Also
+    /// used by callers that compute a value outside the lock.
+    pub(crate) fn compute(&self, calc: impl FnOnce() -> T) -> T {
+        self.try_compute(|| Ok::<_, Infallible>(calc()))
+            .unwrap_or_else(|never| match never {})
+    }
+
+    fn try_compute<E>(&self, calc: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        self.notify(|listener| listener.on_compute_start(&()));
+
+        let started = Instant::now();
+        let result = calc();
+        let elapsed = started.elapsed();
+        self.stats.load(elapsed);
+
+        if let Ok(ref value) = result {
+            self.notify(|listener| listener.on_compute_finish(&(), value, elapsed));
+        }
+
+        result
+    }
+
+    /// counts a value that was replaced because it expired as evicted, and
+    /// reports it to the listener
+    fn expire(&self, expired: Option<Entry<T>>) {
+        if let Some(entry) = expired {
+            self.stats.evict(1);
+            self.notify(|listener| listener.on_removal(&(), &entry.value, RemovalCause::Expired));
+        }
+    }
+
+    fn notify(&self, event: impl FnOnce(&(dyn CacheListener<(), T> + Send + Sync))) {
+        if let Some(ref listener) = self.listener {
+            event(&**listener);
+        }
+    }
+
+    /// returns the cached value, running `calc` first if there is no value
+    /// or it has expired. Only one thread runs `calc` at a time; the others
+    /// block on the lock and then use the value it published. Errors from
+    /// `calc` leave the slot as it was, so the next thread in line tries
+    /// again.
+    ///
+    /// Before blocking on another thread's computation, checks that this
+    /// would not close a cycle of threads waiting on each other's caches.
+    pub(crate) fn get_or_try_init<E, F>(
+        &self,
+        calc: F,
+    ) -> Result<AtomicCacheRef<'_, T>, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        let mut missed = false;
+
+        // a poisoned slot is left to `lock` and the poison policy
+        if !self.lock.is_poisoned() {
+            if let Some(read) = self.try_read() {
+                if is_fresh(read.data(), self.ttl) {
+                    self.stats.hit();
+                    self.notify(|listener| {
+                        listener.on_hit(&(), &read.data().as_ref().unwrap().value)
+                    });
+
+                    return Ok(AtomicCacheRef::new(read));
+                }
+
+                missed = true;
+                self.stats.miss();
+            }
+        }
+
+        let lock = self.lock()?;
+
+        // another thread may have filled the slot while we were waiting for
+        // the lock
+        let read = self.read(&lock);
+        if is_fresh(read.data(), self.ttl) {
+            if !missed {
+                self.stats.hit();
+                self.notify(|listener| listener.on_hit(&(), &read.data().as_ref().unwrap().value));
+            }
+
+            return Ok(AtomicCacheRef::new(read));
+        }
+        drop(read);
+
+        if !missed {
+            self.stats.miss();
+        }
+
+        let lock = self.fill(lock, calc)?;
+        Ok(AtomicCacheRef::new(self.read(&lock)))
+    }
+
+    /// like [`get_or_try_init`], but keeps every reader out and hands out
+    /// mutable access to the value
+    ///
+    /// [`get_or_try_init`]: #method.get_or_try_init
+    pub(crate) fn get_mut_or_try_init<E, F>(
+        &self,
+        calc: F,
+    ) -> Result<WriteGuard<'_, T>, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        let lock = self.lock()?;
+
+        let read = self.read(&lock);
+        let lock = if is_fresh(read.data(), self.ttl) {
+            self.stats.hit();
+            self.notify(|listener| listener.on_hit(&(), &read.data().as_ref().unwrap().value));
+            drop(read);
+
+            lock
+        } else {
+            drop(read);
+            self.stats.miss();
+
+            self.fill(lock, calc)?
+        };
+
+        Ok(self.write(lock))
+    }
+
+    /// mutable access to the value through a unique reference, which needs
+    /// no locking at all
+    pub(crate) fn force_mut<F>(&mut self, calc: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        // settle any poisoning first, so that the data and the listener can
+        // be borrowed separately
+        self.data_mut();
+
+        if is_fresh(self.data.get_mut(), self.ttl) {
+            self.stats.hit();
+
+            if let Some(ref listener) = self.listener {
+                listener.on_hit(&(), &self.data.get_mut().as_ref().unwrap().value);
+            }
+        } else {
+            self.stats.miss();
+
+            let _span = Span::compute(self.data_mut().is_some(), || self.describe());
+            let value = self.compute(calc);
+            let expired = self.data_mut().replace(Entry::new(value));
+            self.expire(expired);
+        }
+
+        &mut self.data_mut().as_mut().unwrap().value
+    }
+
+    /// the stored value, whether or not it has expired
+    pub(crate) fn into_inner(mut self) -> Option<T> {
+        self.data_mut().take().map(|entry| entry.value)
+    }
+
+    /// the stored value behind a unique reference. If a thread panicked
+    /// while holding the lock, this either panics or discards the value,
+    /// depending on the poison policy.
+    fn data_mut(&mut self) -> &mut Option<Entry<T>> {
+        if self.lock.is_poisoned() {
+            match self.poison {
+                PoisonPolicy::Recover => {
+                    self.lock.clear_poison();
+                    *self.data.get_mut() = None;
+                }
+                _ => panic!("poisoned lock: another task failed inside"),
+            }
+        }
+
+        self.data.get_mut()
+    }
+
+    /// runs `calc` and stores its value, on behalf of a thread holding the
+    /// lock that found the slot empty or expired. Readers of an expired
+    /// value carry on until the new one is ready.
+    fn fill<'a, E, F>(
+        &'a self,
+        lock: MutexGuard<'a, ()>,
+        calc: F,
+    ) -> Result<MutexGuard<'a, ()>, InitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        let _initializing = Initializing::enter_shared(self.id(), self.label(), &self.owner)
+            .map_err(InitError::Cycle)?;
+        let stale = self.read(&lock).data().is_some();
+        let _span = Span::compute(stale, || self.describe());
+        let value = self.try_compute(calc).map_err(InitError::Failed)?;
+
+        let mut write = self.write(lock);
+        let expired = write.data_mut().replace(Entry::new(value));
+        let lock = write.into_lock();
+        self.expire(expired);
+
+        Ok(lock)
+    }
+
+    /// counts the current thread in as a reader, unless a writer is active
+    fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let read = self.enter();
+
+        if self.writing.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        Some(read)
+    }
+
+    /// counts the current thread in as a reader while it holds the lock,
+    /// which already keeps writers out
+    fn read(&self, _lock: &MutexGuard<'_, ()>) -> ReadGuard<'_, T> {
+        self.enter()
+    }
+
+    fn enter(&self) -> ReadGuard<'_, T> {
+        let stripe = STRIPE.with(|stripe| *stripe) & (self.readers.len() - 1);
+        let stripe = &self.readers[stripe];
+        stripe.0.fetch_add(1, Ordering::SeqCst);
+
+        ReadGuard { slot: self, stripe }
+    }
+
+    /// keeps new readers out and waits for the current ones to leave, which
+    /// gives the thread holding the lock sole access to the data
+    fn write<'a>(&'a self, lock: MutexGuard<'a, ()>) -> WriteGuard<'a, T> {
+        *self.writer() = Some(thread::current());
+        self.writing.store(true, Ordering::SeqCst);
+        let exclusive = Exclusive(self);
+
+        // a reader that leaves after this check sees `writing` and wakes us
+        while self
+            .readers
+            .iter()
+            .any(|stripe| stripe.0.load(Ordering::SeqCst) != 0)
+        {
+            thread::park();
+        }
+
+        *self.writer() = None;
+        WriteGuard { exclusive, lock }
+    }
+
+    fn writer(&self) -> MutexGuard<'_, Option<Thread>> {
+        // only ever holds a handle to a thread, which stays valid whatever
+        // happened to the thread that stored it
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// takes the lock, checking for cycles before blocking on it. If a
+    /// thread panicked while holding it, the poison policy decides what
+    /// happens.
+    fn lock<E>(&self) -> Result<MutexGuard<'_, ()>, InitError<E>> {
+        let result = match self.lock.try_lock() {
+            Ok(lock) => Ok(lock),
+            Err(TryLockError::WouldBlock) => {
+                let _waiting = Waiting::enter(self.id(), self.label(), &self.owner)
+                    .map_err(InitError::Cycle)?;
+                let _span = Span::wait(|| self.describe());
+                self.lock.lock()
+            }
+            Err(TryLockError::Poisoned(err)) => Err(err),
+        };
+
+        match result {
+            Ok(lock) => Ok(lock),
+            Err(err) => match self.poison {
+                PoisonPolicy::Panic => panic!("{}", err),
+                PoisonPolicy::Error => Err(InitError::Poisoned),
+                PoisonPolicy::Recover => {
+                    let lock = err.into_inner();
+                    self.lock.clear_poison();
+
+                    let mut write = self.write(lock);
+                    *write.data_mut() = None;
+
+                    Ok(write.into_lock())
+                }
+            },
+        }
+    }
+
+    pub(crate) fn take(&self) -> Option<T> {
+        let value = self
+            .write_unpoisoned()
+            .data_mut()
+            .take()
+            .map(|entry| entry.value);
+
+        if let Some(ref value) = value {
+            self.stats.invalidate(1);
+            self.notify(|listener| listener.on_removal(&(), value, RemovalCause::Invalidated));
+        }
+
+        value
+    }
+
+    pub(crate) fn set(&self, value: T) {
+        let replaced = self
+            .write_unpoisoned()
+            .data_mut()
+            .replace(Entry::new(value));
+
+        if let Some(entry) = replaced {
+            self.notify(|listener| listener.on_removal(&(), &entry.value, RemovalCause::Replaced));
+        }
+    }
+
+    /// takes the lock and waits for the readers to leave, ignoring
+    /// poisoning. Only for callers that are about to overwrite the value
+    /// anyway, which makes the slot healthy again.
+    fn write_unpoisoned(&self) -> WriteGuard<'_, T> {
+        let lock = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.lock.clear_poison();
+
+        self.write(lock)
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.label().describe()
+    }
+
+    fn label(&self) -> Label {
+        label::<T, _>(&self.name, self)
+    }
+}
+
+/// counts a reader in on one stripe of an [`AtomicSlot`] until dropped
+///
+/// [`AtomicSlot`]: ./struct.AtomicSlot.html
+pub(crate) struct ReadGuard<'a, T> {
+    slot: &'a AtomicSlot<T>,
+    stripe: &'a Stripe,
+}
+
+impl<'a, T> ReadGuard<'a, T> {
+    pub(crate) fn data(&self) -> &Option<Entry<T>> {
+        // SAFETY: writers wait for every reader that is counted in to leave
+        // before they touch the data
+        unsafe { &*self.slot.data.get() }
+    }
+}
+
+impl<'a, T> Drop for ReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.stripe.0.fetch_sub(1, Ordering::SeqCst);
+
+        if self.slot.writing.load(Ordering::SeqCst) {
+            if let Some(ref writer) = *self.slot.writer() {
+                writer.unpark();
+            }
+        }
+    }
+}
+
+/// keeps readers out of an [`AtomicSlot`] until dropped
+///
+/// [`AtomicSlot`]: ./struct.AtomicSlot.html
+struct Exclusive<'a, T>(&'a AtomicSlot<T>);
+
+impl<'a, T> Drop for Exclusive<'a, T> {
+    fn drop(&mut self) {
+        self.0.writing.store(false, Ordering::SeqCst);
+    }
+}
+
+/// sole access to the data of an [`AtomicSlot`], held by the thread
+/// holding its lock once every reader has left
+///
+/// [`AtomicSlot`]: ./struct.AtomicSlot.html
+pub(crate) struct WriteGuard<'a, T> {
+    // dropped first, so that readers are let back in before another writer
+    // can take the lock
+    exclusive: Exclusive<'a, T>,
+    lock: MutexGuard<'a, ()>,
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    pub(crate) fn data(&self) -> &Option<Entry<T>> {
+        // SAFETY: every reader has left, and no other writer can get in
+        // while we hold the lock
+        unsafe { &*self.exclusive.0.data.get() }
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut Option<Entry<T>> {
+        // SAFETY: as in `data`
+        unsafe { &mut *self.exclusive.0.data.get() }
+    }
+
+    /// lets readers back in, but keeps holding the lock
+    fn into_lock(self) -> MutexGuard<'a, ()> {
+        let WriteGuard { exclusive, lock } = self;
+        drop(exclusive);
+
+        lock
+    }
+}
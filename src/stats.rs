@@ -0,0 +1,286 @@
+//! Opt-in statistics on how often caches are hit and how long their
+//! closures take.
+
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// a snapshot of the statistics a cache has recorded since it was told to
+/// record them. Every cache type has a `record_stats` method that turns
+/// recording on, and a `stats` method that returns a snapshot.
+///
+/// Snapshots of several caches, or of the same cache at different times,
+/// can be added up.
+/// ```
+/// # use cache::Cache;
+/// let cache = Cache::new(Box::new(|| 55)).record_stats();
+///
+/// cache.get();
+/// cache.get();
+/// cache.invalidate();
+/// cache.get();
+///
+/// let stats = cache.stats().unwrap();
+/// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 2, 2));
+/// assert_eq!(stats.invalidations, 1);
+/// assert_eq!(stats.hit_rate(), 1.0 / 3.0);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// the number of lookups that found a value
+    pub hits: u64,
+    /// the number of lookups that found no value, or an expired one, and
+    /// had to wait for it to be computed
+    pub misses: u64,
+    /// the number of times the closure ran to completion, whether or not it
+    /// returned an error
+    pub loads: u64,
+    /// the time spent running the closure, added up over every load
+    pub total_load_time: Duration,
+    /// the time taken by the slowest load
+    pub max_load_time: Duration,
+    /// the number of values dropped because they expired or to make room
+    /// for other values
+    pub evictions: u64,
+    /// the number of values dropped because the cache was invalidated
+    pub invalidations: u64,
+}
+
+impl CacheStats {
+    /// the number of lookups, both hits and misses
+    pub fn requests(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// the share of lookups that found a value, which is 1 for a cache that
+    /// has not been looked up yet
+    pub fn hit_rate(&self) -> f64 {
+        match self.requests() {
+            0 => 1.0,
+            requests => self.hits as f64 / requests as f64,
+        }
+    }
+
+    /// the time a load took on average, or zero if there were no loads
+    pub fn average_load_time(&self) -> Duration {
+        match self.loads {
+            0 => Duration::ZERO,
+            loads => self.total_load_time.div_f64(loads as f64),
+        }
+    }
+}
+
+impl Add for CacheStats {
+    type Output = CacheStats;
+
+    fn add(self, other: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            loads: self.loads + other.loads,
+            total_load_time: self.total_load_time + other.total_load_time,
+            max_load_time: self.max_load_time.max(other.max_load_time),
+            evictions: self.evictions + other.evictions,
+            invalidations: self.invalidations + other.invalidations,
+        }
+    }
+}
+
+/// statistics recorded by a non-thread-safe cache, or by a thread-safe one
+/// whose counters are only touched while it holds a lock anyway. Records
+/// nothing until enabled.
+#[derive(Default)]
+pub(crate) struct Stats(Option<Cell<CacheStats>>);
+
+impl Stats {
+    pub(crate) fn enable(&mut self) {
+        self.0 = Some(Cell::default());
+    }
+
+    pub(crate) fn snapshot(&self) -> Option<CacheStats> {
+        self.0.as_ref().map(Cell::get)
+    }
+
+    pub(crate) fn hit(&self) {
+        self.record(|stats| stats.hits += 1);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.record(|stats| stats.misses += 1);
+    }
+
+    pub(crate) fn load(&self, elapsed: Duration) {
+        self.record(|stats| {
+            stats.loads += 1;
+            stats.total_load_time += elapsed;
+            stats.max_load_time = stats.max_load_time.max(elapsed);
+        });
+    }
+
+    pub(crate) fn evict(&self, count: u64) {
+        self.record(|stats| stats.evictions += count);
+    }
+
+    pub(crate) fn invalidate(&self, count: u64) {
+        self.record(|stats| stats.invalidations += count);
+    }
+
+    /// runs `calc`, recording it as a load
+    pub(crate) fn time<R>(&self, calc: impl FnOnce() -> R) -> R {
+        if self.0.is_none() {
+            return calc();
+        }
+
+        let started = Instant::now();
+        let value = calc();
+        self.load(started.elapsed());
+
+        value
+    }
+
+    fn record(&self, update: impl FnOnce(&mut CacheStats)) {
+        if let Some(ref cell) = self.0 {
+            let mut stats = cell.get();
+            update(&mut stats);
+            cell.set(stats);
+        }
+    }
+}
+
+/// statistics recorded by a thread-safe cache without taking any lock.
+/// Records nothing until enabled.
+#[derive(Default)]
+pub(crate) struct AtomicStats(Option<Counters>);
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    loads: AtomicU64,
+    load_nanos: AtomicU64,
+    max_load_nanos: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl AtomicStats {
+    pub(crate) fn enable(&mut self) {
+        self.0 = Some(Counters::default());
+    }
+
+    /// the counters as they are right now. Counters updated by other
+    /// threads while the snapshot is taken may or may not be included.
+    pub(crate) fn snapshot(&self) -> Option<CacheStats> {
+        self.0.as_ref().map(|counters| CacheStats {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            loads: counters.loads.load(Ordering::Relaxed),
+            total_load_time: Duration::from_nanos(counters.load_nanos.load(Ordering::Relaxed)),
+            max_load_time: Duration::from_nanos(counters.max_load_nanos.load(Ordering::Relaxed)),
+            evictions: counters.evictions.load(Ordering::Relaxed),
+            invalidations: counters.invalidations.load(Ordering::Relaxed),
+        })
+    }
+
+    pub(crate) fn hit(&self) {
+        self.add(|counters| &counters.hits, 1);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.add(|counters| &counters.misses, 1);
+    }
+
+    pub(crate) fn load(&self, elapsed: Duration) {
+        if let Some(ref counters) = self.0 {
+            let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+
+            counters.loads.fetch_add(1, Ordering::Relaxed);
+            counters.load_nanos.fetch_add(nanos, Ordering::Relaxed);
+            counters.max_load_nanos.fetch_max(nanos, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn evict(&self, count: u64) {
+        self.add(|counters| &counters.evictions, count);
+    }
+
+    pub(crate) fn invalidate(&self, count: u64) {
+        self.add(|counters| &counters.invalidations, count);
+    }
+
+    /// runs `calc`, recording it as a load
+    pub(crate) fn time<R>(&self, calc: impl FnOnce() -> R) -> R {
+        if self.0.is_none() {
+            return calc();
+        }
+
+        let started = Instant::now();
+        let value = calc();
+        self.load(started.elapsed());
+
+        value
+    }
+
+    fn add(&self, counter: impl FnOnce(&Counters) -> &AtomicU64, count: u64) {
+        if let Some(ref counters) = self.0 {
+            counter(counters).fetch_add(count, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AtomicCache, Cache, CacheMap};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_atomic_counts_every_lookup() {
+        let calc: Box<dyn Fn() -> i32 + Send + Sync> = Box::new(|| {
+            thread::sleep(Duration::from_millis(20));
+            55
+        });
+        let cache = Arc::new(AtomicCache::new(calc).record_stats());
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || *cache.get())
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 55);
+        }
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.requests(), 8);
+        assert_eq!(stats.loads, 1);
+        assert!(stats.max_load_time >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_evictions_are_counted() {
+        let cache = Cache::with_ttl(Box::new(|| 55), Duration::from_millis(5)).record_stats();
+
+        cache.get();
+        thread::sleep(Duration::from_millis(10));
+        cache.get();
+        assert_eq!(cache.stats().unwrap().evictions, 1);
+
+        let calc: Box<dyn Fn(&u64) -> u64> = Box::new(|n| n * n);
+        let squares = CacheMap::with_capacity(calc, 2).record_stats();
+
+        for n in 0..5 {
+            squares.get(&n);
+        }
+
+        squares.invalidate_all();
+
+        let stats = squares.stats().unwrap();
+        assert_eq!((stats.evictions, stats.invalidations), (3, 2));
+    }
+}
@@ -0,0 +1,327 @@
+//! Storage shared by the keyed caches. The public map types pair a store
+//! with a closure and a way of waiting on keys that are being computed.
+
+use crate::admission::TinyLfu;
+use crate::listener::RemovalCause;
+use crate::policy::EvictionPolicy;
+use crate::stats::{CacheStats, Stats};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+use std::time::Duration;
+
+/// the state of a single key
+pub(crate) enum State<V, L> {
+    /// the value has been computed
+    Ready(V),
+    /// the closure is running for this key. `L` identifies the computation,
+    /// so that it can tell whether the key was invalidated in the meantime.
+    Loading(L),
+}
+
+/// a key's state, along with the weight of its value. Keys that are still
+/// loading weigh nothing.
+struct Entry<V, L> {
+    state: State<V, L>,
+    weight: usize,
+}
+
+/// the keys of a keyed cache, along with the eviction policy that decides
+/// which values to drop once the store is over capacity. The capacity is a
+/// budget for the total weight of the computed values. Only computed values
+/// are reported to the policy; keys that are still loading are never
+/// evicted.
+///
+/// With an admission filter, a new value that would take the store over
+/// capacity is only stored if its key is asked for more often than the key
+/// the policy picked for eviction.
+///
+/// The statistics of the store are only touched by whoever has it borrowed
+/// or locked, so they need no atomics. Dropped values are kept for the
+/// listener of the map, which is only told about them once the store has
+/// been let go of.
+pub(crate) struct Store<K, V, L, P> {
+    entries: HashMap<K, Entry<V, L>>,
+    policy: P,
+    admission: Option<TinyLfu>,
+    capacity: Option<usize>,
+    /// the number of computed values
+    len: usize,
+    /// the total weight of the computed values
+    weight: usize,
+    stats: Stats,
+    removals: Option<Vec<(K, V, RemovalCause)>>,
+}
+
+impl<K, V, L, P> Store<K, V, L, P>
+where
+    K: Eq + Hash + Clone,
+    P: EvictionPolicy<K>,
+{
+    pub(crate) fn new(capacity: Option<usize>, mut policy: P) -> Self {
+        policy.on_capacity(capacity);
+
+        Store {
+            entries: HashMap::new(),
+            policy,
+            admission: None,
+            capacity,
+            len: 0,
+            weight: 0,
+            stats: Stats::default(),
+            removals: None,
+        }
+    }
+
+    pub(crate) fn enable_admission(&mut self) {
+        self.admission = Some(TinyLfu::new(self.capacity));
+    }
+
+    pub(crate) fn enable_stats(&mut self) {
+        self.stats.enable();
+    }
+
+    pub(crate) fn stats(&self) -> Option<CacheStats> {
+        self.stats.snapshot()
+    }
+
+    /// starts keeping the values that the store drops, for
+    /// [`take_removals`]
+    ///
+    /// [`take_removals`]: #method.take_removals
+    pub(crate) fn track_removals(&mut self) {
+        self.removals = Some(Vec::new());
+    }
+
+    /// the values dropped since the last call, along with their keys and
+    /// the reason they were dropped. Always empty unless removals are
+    /// tracked.
+    pub(crate) fn take_removals(&mut self) -> Vec<(K, V, RemovalCause)> {
+        match self.removals {
+            Some(ref mut removals) => mem::take(removals),
+            None => Vec::new(),
+        }
+    }
+
+    /// records that computing a value took `elapsed`
+    pub(crate) fn record_load(&self, elapsed: Duration) {
+        self.stats.load(elapsed);
+    }
+
+    /// the state of `key`, telling the policy that its value has been read
+    /// and the admission filter that it has been asked for
+    pub(crate) fn get(&mut self, key: &K) -> Option<&State<V, L>> {
+        if let Some(ref mut admission) = self.admission {
+            admission.record(key);
+        }
+
+        let state = self.entries.get(key).map(|entry| &entry.state);
+
+        if let Some(State::Ready(_)) = state {
+            self.policy.on_access(key);
+            self.stats.hit();
+        } else {
+            self.stats.miss();
+        }
+
+        state
+    }
+
+    /// whether `key` has a computed value, without counting as a read
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        matches!(
+            self.entries.get(key),
+            Some(Entry {
+                state: State::Ready(_),
+                ..
+            })
+        )
+    }
+
+    /// the computation running for `key`, if any
+    pub(crate) fn loading(&self, key: &K) -> Option<&L> {
+        match self.entries.get(key) {
+            Some(Entry {
+                state: State::Loading(load),
+                ..
+            }) => Some(load),
+            _ => None,
+        }
+    }
+
+    /// marks `key` as being computed, dropping any value it had
+    pub(crate) fn load(&mut self, key: K, load: L) {
+        let entry = Entry {
+            state: State::Loading(load),
+            weight: 0,
+        };
+        let replaced = self.entries.insert(key.clone(), entry);
+        self.forget(&key, replaced, RemovalCause::Replaced);
+    }
+
+    /// stores the value of `key`, which weighs `weight`, then evicts values
+    /// until the store is within capacity. Returns `false` if the value was
+    /// heavier than the whole capacity, turned away by the admission filter
+    /// or evicted straight away. A value that is not stored still replaces
+    /// the one `key` had before.
+    pub(crate) fn insert(&mut self, key: K, value: V, weight: usize) -> bool {
+        if self.capacity.is_some_and(|capacity| weight > capacity) {
+            self.remove(&key, RemovalCause::Replaced);
+            return false;
+        }
+
+        let entry = Entry {
+            state: State::Ready(value),
+            weight,
+        };
+        let replaced = self.entries.insert(key.clone(), entry);
+        let replaced = match replaced {
+            Some(Entry {
+                state: State::Ready(old),
+                weight,
+            }) => {
+                self.weight -= weight;
+                self.removed(&key, old, RemovalCause::Replaced);
+                true
+            }
+            _ => false,
+        };
+
+        if !replaced {
+            self.len += 1;
+        }
+
+        self.weight += weight;
+        self.policy.on_insert(&key);
+
+        let stored = if replaced {
+            self.evict(None)
+        } else {
+            self.evict(Some(key))
+        };
+        self.policy.on_settled();
+
+        stored
+    }
+
+    pub(crate) fn remove(&mut self, key: &K, cause: RemovalCause) {
+        let removed = self.entries.remove(key);
+        self.forget(key, removed, cause);
+    }
+
+    /// removes the value of `key` on behalf of the user, as opposed to
+    /// making room or cleaning up after a load
+    pub(crate) fn invalidate(&mut self, key: &K) {
+        if self.contains_key(key) {
+            self.stats.invalidate(1);
+        }
+
+        self.remove(key, RemovalCause::Invalidated);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.stats.invalidate(self.len as u64);
+
+        for (key, entry) in self.entries.drain() {
+            if let State::Ready(value) = entry.state {
+                self.policy.on_remove(&key);
+
+                if let Some(ref mut removals) = self.removals {
+                    removals.push((key, value, RemovalCause::Invalidated));
+                }
+            }
+        }
+
+        self.len = 0;
+        self.weight = 0;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+        self.policy.on_capacity(capacity);
+
+        if let Some(ref mut admission) = self.admission {
+            admission.resize(capacity);
+        }
+
+        self.evict(None);
+    }
+
+    /// if `removed` held the value of `key`, tells the policy that it is
+    /// gone and stops counting it
+    fn forget(&mut self, key: &K, removed: Option<Entry<V, L>>, cause: RemovalCause) {
+        if let Some(Entry {
+            state: State::Ready(value),
+            weight,
+        }) = removed
+        {
+            self.policy.on_remove(key);
+            self.len -= 1;
+            self.weight -= weight;
+            self.removed(key, value, cause);
+        }
+    }
+
+    /// keeps `value` for the listener, if removals are tracked
+    fn removed(&mut self, key: &K, value: V, cause: RemovalCause) {
+        if let Some(ref mut removals) = self.removals {
+            removals.push((key.clone(), value, cause));
+        }
+    }
+
+    /// evicts values until the store is within capacity. If `candidate` is
+    /// a key that was just added, the admission filter gets to choose
+    /// between it and the first victim. Returns `false` if the candidate did
+    /// not survive.
+    fn evict(&mut self, candidate: Option<K>) -> bool {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return true,
+        };
+        let mut contender = candidate.clone();
+
+        while self.weight > capacity {
+            let victim = match self.policy.evict() {
+                Some(key) => key,
+                None => break,
+            };
+
+            if let (Some(contender), Some(admission)) = (contender.take(), &self.admission) {
+                if contender != victim && !admission.admit(&contender, &victim) {
+                    self.policy.restore(&victim);
+                    self.remove(&contender, RemovalCause::Evicted);
+                    self.stats.evict(1);
+
+                    continue;
+                }
+            }
+
+            if self.contains_key(&victim) {
+                if let Some(Entry {
+                    state: State::Ready(value),
+                    weight,
+                }) = self.entries.remove(&victim)
+                {
+                    self.len -= 1;
+                    self.weight -= weight;
+                    self.stats.evict(1);
+                    self.removed(&victim, value, RemovalCause::Evicted);
+                }
+            }
+        }
+
+        candidate.is_none_or(|key| self.contains_key(&key))
+    }
+}
@@ -0,0 +1,171 @@
+//! Spans emitted through the `tracing` crate when the `tracing` feature is
+//! enabled. Without the feature, every span is an empty guard that compiles
+//! away.
+//!
+//! All spans are at the debug level and carry a `cache` field with the name
+//! the cache was given, or a description of its type and address if it was
+//! not given one:
+//!
+//! - `init` covers the closure computing a value the cache does not have
+//! - `recompute` covers the closure replacing a value that expired, or one
+//!   being refreshed
+//! - `wait` covers a thread blocking until another thread has computed the
+//!   value it asked for
+
+/// a span that stays entered until it is dropped
+#[cfg(feature = "tracing")]
+pub(crate) struct Span {
+    _entered: tracing::span::EnteredSpan,
+}
+
+/// a span that stays entered until it is dropped
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+#[cfg(feature = "tracing")]
+impl Span {
+    pub(crate) fn init(cache: impl FnOnce() -> String) -> Self {
+        Span::enter(
+            tracing::debug_span!("init", cache = tracing::field::Empty),
+            cache,
+        )
+    }
+
+    pub(crate) fn recompute(cache: impl FnOnce() -> String) -> Self {
+        Span::enter(
+            tracing::debug_span!("recompute", cache = tracing::field::Empty),
+            cache,
+        )
+    }
+
+    pub(crate) fn wait(cache: impl FnOnce() -> String) -> Self {
+        Span::enter(
+            tracing::debug_span!("wait", cache = tracing::field::Empty),
+            cache,
+        )
+    }
+
+    /// `init` or `recompute`, depending on whether the value being replaced
+    /// is `stale` or missing altogether
+    pub(crate) fn compute(stale: bool, cache: impl FnOnce() -> String) -> Self {
+        if stale {
+            Span::recompute(cache)
+        } else {
+            Span::init(cache)
+        }
+    }
+
+    /// only describes the cache if a subscriber is interested in the span,
+    /// since that allocates
+    fn enter(span: tracing::Span, cache: impl FnOnce() -> String) -> Self {
+        if !span.is_disabled() {
+            span.record("cache", cache().as_str());
+        }
+
+        Span {
+            _entered: span.entered(),
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn init(_cache: impl FnOnce() -> String) -> Self {
+        Span
+    }
+
+    pub(crate) fn recompute(_cache: impl FnOnce() -> String) -> Self {
+        Span
+    }
+
+    pub(crate) fn wait(_cache: impl FnOnce() -> String) -> Self {
+        Span
+    }
+
+    pub(crate) fn compute(_stale: bool, _cache: impl FnOnce() -> String) -> Self {
+        Span
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{AtomicCache, Cache};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// the name and `cache` field of every span created while it is the
+    /// default subscriber
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<(&'static str, String)>>>);
+
+    struct CacheField<'a>(&'a mut String);
+
+    impl<'a> Visit for CacheField<'a> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "cache" {
+                *self.0 = value.to_string();
+            }
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            spans.push((span.metadata().name(), String::new()));
+
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.0.lock().unwrap();
+            let index = span.into_u64() as usize - 1;
+            values.record(&mut CacheField(&mut spans[index].1));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_spans_are_tagged_with_the_name() {
+        let spans = Spans::default();
+
+        tracing::subscriber::with_default(spans.clone(), || {
+            let cache = Cache::new(Box::new(|| 55)).named("answer");
+            cache.get();
+            cache.get();
+
+            let calc: Box<dyn Fn() -> Arc<i32> + Send + Sync> = Box::new(|| Arc::new(55));
+            let cache = AtomicCache::new(calc).named("shared");
+            cache.get();
+            cache.refresh();
+        });
+
+        let spans = spans.0.lock().unwrap();
+        let spans: Vec<_> = spans
+            .iter()
+            .map(|(name, cache)| (*name, cache.as_str()))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                ("init", "answer"),
+                ("init", "shared"),
+                ("recompute", "shared")
+            ]
+        );
+    }
+}
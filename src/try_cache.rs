@@ -0,0 +1,553 @@
+//! Caches whose closure can fail.
+
+use crate::slot::{AtomicSlot, InitError, Slot};
+use crate::{AtomicCacheRef, CacheRef, CacheStats, PoisonPolicy, TryGetError};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// a variant of [`Cache`] whose closure returns a [`Result`]. Only successful
+/// values are cached; an error is handed back to the caller and the next call
+/// to [`try_get`] runs the closure again.
+///
+/// As with [`Cache`], the closure type `F` defaults to a boxed closure.
+///
+/// [`Cache`]: ./struct.Cache.html
+/// [`Result`]: https://doc.rust-lang.org/std/result/enum.Result.html
+/// [`try_get`]: #method.try_get
+pub struct TryCache<T, E, F = Box<dyn Fn() -> Result<T, E>>> {
+    calc: F,
+    slot: Slot<T>,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<T, E, F> TryCache<T, E, F>
+where
+    F: Fn() -> Result<T, E>,
+{
+    /// Constructs a new TryCache using a closure that lazily evaluates to
+    /// the value that will be cached, or to an error.
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    pub fn new(calc: F) -> Self {
+        TryCache {
+            calc,
+            slot: Slot::new(None),
+            error: PhantomData,
+        }
+    }
+
+    /// Constructs a new TryCache whose value expires `ttl` after it was
+    /// computed. See [`Cache::with_ttl`] for details.
+    /// ```
+    /// # use cache::TryCache;
+    /// # use std::time::Duration;
+    /// let cache = TryCache::with_ttl(
+    ///     Box::new(|| "55".parse::<i32>()),
+    ///     Duration::from_secs(60),
+    /// );
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`Cache::with_ttl`]: ./struct.Cache.html#method.with_ttl
+    pub fn with_ttl(calc: F, ttl: Duration) -> Self {
+        TryCache {
+            calc,
+            slot: Slot::new(Some(ttl)),
+            error: PhantomData,
+        }
+    }
+
+    /// gets a reference to the cached value, computing it first if it
+    /// does not exist or has expired. If the closure fails, its error is
+    /// returned as [`TryGetError::Failed`] and nothing is cached.
+    /// ```
+    /// # use cache::{TryCache, TryGetError};
+    /// # use std::cell::Cell;
+    /// # use std::rc::Rc;
+    /// let attempts = Rc::new(Cell::new(0));
+    /// let counter = Rc::clone(&attempts);
+    /// let cache = TryCache::new(Box::new(move || {
+    ///     counter.set(counter.get() + 1);
+    ///
+    ///     if counter.get() < 2 {
+    ///         Err("not yet")
+    ///     } else {
+    ///         Ok(55)
+    ///     }
+    /// }));
+    ///
+    /// assert_eq!(cache.try_get().err(), Some(TryGetError::Failed("not yet")));
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// assert_eq!(attempts.get(), 2);
+    /// ```
+    ///
+    /// If the closure, directly or through other caches, calls `try_get` on
+    /// this same cache, the inner call returns [`TryGetError::Cycle`].
+    ///
+    /// [`TryGetError::Failed`]: ./enum.TryGetError.html#variant.Failed
+    /// [`TryGetError::Cycle`]: ./enum.TryGetError.html#variant.Cycle
+    pub fn try_get(&self) -> Result<CacheRef<'_, T>, TryGetError<E>> {
+        match self.slot.get_or_try_init(&self.calc) {
+            Ok(value) => Ok(value),
+            Err(InitError::Cycle(err)) => Err(TryGetError::Cycle(err)),
+            Err(InitError::Poisoned) => unreachable!("a TryCache has no lock to poison"),
+            Err(InitError::Failed(err)) => Err(TryGetError::Failed(err)),
+        }
+    }
+
+    /// gives the cache a name, which is used to identify it in a
+    /// [`CycleError`]
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>())).named("answer");
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.slot.set_name(name.into());
+        self
+    }
+
+    /// starts recording how often the cache is hit and how long the closure
+    /// takes, which [`stats`] then reports
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>())).record_stats();
+    ///
+    /// cache.try_get().unwrap();
+    /// cache.try_get().unwrap();
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.slot.enable_stats();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.slot.stats()
+    }
+
+    /// drops the cached value, if any, so that the next call to [`try_get`]
+    /// recomputes it
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`CacheRef`] to the cached value is still alive.
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// cache.invalidate();
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`try_get`]: #method.try_get
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn invalidate(&self) {
+        self.take();
+    }
+
+    /// removes the cached value, if any, and returns it. The next call to
+    /// [`try_get`] recomputes the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`CacheRef`] to the cached value is still alive.
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(cache.take(), None);
+    /// cache.try_get().unwrap();
+    /// assert_eq!(cache.take(), Some(55));
+    /// ```
+    ///
+    /// [`try_get`]: #method.try_get
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn take(&self) -> Option<T> {
+        self.slot.take()
+    }
+
+    /// replaces the cached value with `value`, without running the closure
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [`CacheRef`] to the cached value is still alive.
+    /// ```
+    /// # use cache::TryCache;
+    /// let cache = TryCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// cache.set(10);
+    /// assert_eq!(*cache.try_get().unwrap(), 10);
+    /// ```
+    ///
+    /// [`CacheRef`]: ./struct.CacheRef.html
+    pub fn set(&self, value: T) {
+        self.slot.set(value);
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.slot.describe()
+    }
+}
+
+/// a thread-safe variant of [`TryCache`]
+///
+/// Like [`AtomicCache`], only one thread runs the closure at a time. If it
+/// fails, that thread gets the error back and the next waiting thread runs
+/// the closure itself; a failure never poisons the cache. A closure that
+/// panics does, and what happens next is up to the [`PoisonPolicy`] set with
+/// [`on_poison`].
+///
+/// [`TryCache`]: ./struct.TryCache.html
+/// [`AtomicCache`]: ./struct.AtomicCache.html
+/// [`PoisonPolicy`]: ./enum.PoisonPolicy.html
+/// [`on_poison`]: #method.on_poison
+pub struct TryAtomicCache<T, E, F = Box<dyn Fn() -> Result<T, E> + Send + Sync>> {
+    calc: F,
+    slot: AtomicSlot<T>,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<T, E, F> TryAtomicCache<T, E, F>
+where
+    F: Fn() -> Result<T, E>,
+{
+    /// Constructs a new TryAtomicCache using a closure that lazily evaluates
+    /// to the value that will be cached, or to an error.
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    pub fn new(calc: F) -> Self {
+        TryAtomicCache {
+            calc,
+            slot: AtomicSlot::new(None),
+            error: PhantomData,
+        }
+    }
+
+    /// Constructs a new TryAtomicCache whose value expires `ttl` after it was
+    /// computed. See [`AtomicCache::with_ttl`] for details.
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// # use std::time::Duration;
+    /// let cache = TryAtomicCache::with_ttl(
+    ///     Box::new(|| "55".parse::<i32>()),
+    ///     Duration::from_secs(60),
+    /// );
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`AtomicCache::with_ttl`]: ./struct.AtomicCache.html#method.with_ttl
+    pub fn with_ttl(calc: F, ttl: Duration) -> Self {
+        TryAtomicCache {
+            calc,
+            slot: AtomicSlot::new(Some(ttl)),
+            error: PhantomData,
+        }
+    }
+
+    /// gets a reference to the cached value, computing it first if it
+    /// does not exist or has expired. If the closure fails, its error is
+    /// returned as [`TryGetError::Failed`] and nothing is cached.
+    /// ```
+    /// # use cache::{TryAtomicCache, TryGetError};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// let attempts = Arc::new(AtomicUsize::new(0));
+    /// let counter = Arc::clone(&attempts);
+    /// let cache = TryAtomicCache::new(Box::new(move || {
+    ///     if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+    ///         Err("not yet")
+    ///     } else {
+    ///         Ok(55)
+    ///     }
+    /// }));
+    ///
+    /// assert_eq!(cache.try_get().err(), Some(TryGetError::Failed("not yet")));
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    /// ```
+    ///
+    /// Returns [`TryGetError::Cycle`] if the closure, directly or through
+    /// other caches, calls `try_get` on this same cache, or if waiting for
+    /// another thread's computation would deadlock, and
+    /// [`TryGetError::Poisoned`] if the cache was poisoned and configured with
+    /// [`PoisonPolicy::Error`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache was poisoned and configured with
+    /// [`PoisonPolicy::Panic`], which is the default.
+    ///
+    /// [`TryGetError::Failed`]: ./enum.TryGetError.html#variant.Failed
+    /// [`TryGetError::Cycle`]: ./enum.TryGetError.html#variant.Cycle
+    /// [`TryGetError::Poisoned`]: ./enum.TryGetError.html#variant.Poisoned
+    /// [`PoisonPolicy::Error`]: ./enum.PoisonPolicy.html#variant.Error
+    /// [`PoisonPolicy::Panic`]: ./enum.PoisonPolicy.html#variant.Panic
+    pub fn try_get(&self) -> Result<AtomicCacheRef<'_, T>, TryGetError<E>> {
+        match self.slot.get_or_try_init(&self.calc) {
+            Ok(value) => Ok(value),
+            Err(InitError::Cycle(err)) => Err(TryGetError::Cycle(err)),
+            Err(InitError::Poisoned) => Err(TryGetError::Poisoned),
+            Err(InitError::Failed(err)) => Err(TryGetError::Failed(err)),
+        }
+    }
+
+    /// chooses what happens when a thread panics while running the closure,
+    /// which poisons the lock around the value. Defaults to
+    /// [`PoisonPolicy::Panic`]. Errors returned by the closure never poison
+    /// the cache.
+    /// ```
+    /// # use cache::{PoisonPolicy, TryAtomicCache};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// let calls = AtomicUsize::new(0);
+    /// let cache = TryAtomicCache::new(|| {
+    ///     if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+    ///         panic!("first attempt fails");
+    ///     }
+    ///
+    ///     "55".parse::<i32>()
+    /// })
+    /// .on_poison(PoisonPolicy::Recover);
+    ///
+    /// std::thread::scope(|s| {
+    ///     assert!(s.spawn(|| cache.try_get().is_ok()).join().is_err());
+    /// });
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`PoisonPolicy::Panic`]: ./enum.PoisonPolicy.html#variant.Panic
+    pub fn on_poison(mut self, policy: PoisonPolicy) -> Self {
+        self.slot.set_poison_policy(policy);
+        self
+    }
+
+    /// gives the cache a name, which is used to identify it in a
+    /// [`CycleError`]
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>())).named("answer");
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`CycleError`]: ./struct.CycleError.html
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.slot.set_name(name.into());
+        self
+    }
+
+    /// starts recording how often the cache is hit and how long the closure
+    /// takes, which [`stats`] then reports. The counters are
+    /// atomic, so recording takes no lock, but every lookup does update a
+    /// counter shared by all threads
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>())).record_stats();
+    ///
+    /// cache.try_get().unwrap();
+    /// cache.try_get().unwrap();
+    ///
+    /// let stats = cache.stats().unwrap();
+    /// assert_eq!((stats.hits, stats.misses, stats.loads), (1, 1, 1));
+    /// ```
+    ///
+    /// [`stats`]: #method.stats
+    pub fn record_stats(mut self) -> Self {
+        self.slot.enable_stats();
+        self
+    }
+
+    /// a snapshot of the statistics recorded so far, or `None` if the cache
+    /// was not told to [`record_stats`]
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(cache.stats(), None);
+    /// ```
+    ///
+    /// [`record_stats`]: #method.record_stats
+    pub fn stats(&self) -> Option<CacheStats> {
+        self.slot.stats()
+    }
+
+    /// drops the cached value, if any, so that the next call to [`try_get`]
+    /// recomputes it
+    ///
+    /// Blocks until every outstanding [`AtomicCacheRef`] has been dropped, so
+    /// calling this while the current thread still holds one will deadlock.
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// cache.invalidate();
+    /// assert_eq!(*cache.try_get().unwrap(), 55);
+    /// ```
+    ///
+    /// [`try_get`]: #method.try_get
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn invalidate(&self) {
+        self.take();
+    }
+
+    /// removes the cached value, if any, and returns it. The next call to
+    /// [`try_get`] recomputes the value.
+    ///
+    /// Blocks until every outstanding [`AtomicCacheRef`] has been dropped, so
+    /// calling this while the current thread still holds one will deadlock.
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// assert_eq!(cache.take(), None);
+    /// cache.try_get().unwrap();
+    /// assert_eq!(cache.take(), Some(55));
+    /// ```
+    ///
+    /// [`try_get`]: #method.try_get
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn take(&self) -> Option<T> {
+        self.slot.take()
+    }
+
+    /// replaces the cached value with `value`, without running the closure
+    ///
+    /// Blocks until every outstanding [`AtomicCacheRef`] has been dropped, so
+    /// calling this while the current thread still holds one will deadlock.
+    /// ```
+    /// # use cache::TryAtomicCache;
+    /// let cache = TryAtomicCache::new(Box::new(|| "55".parse::<i32>()));
+    ///
+    /// cache.set(10);
+    /// assert_eq!(*cache.try_get().unwrap(), 10);
+    /// ```
+    ///
+    /// [`AtomicCacheRef`]: ./struct.AtomicCacheRef.html
+    pub fn set(&self, value: T) {
+        self.slot.set(value);
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        self.slot.describe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    #[test]
+    fn test_atomic_error_is_not_cached() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(4));
+
+        let counter = Arc::clone(&attempts);
+        let cache = Arc::new(TryAtomicCache::new(Box::new(move || {
+            // the first two attempts fail, every later one succeeds
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(()),
+                n => Ok(n),
+            }
+        })));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let barrier = Arc::clone(&barrier);
+
+                thread::spawn(move || {
+                    barrier.wait();
+                    cache.try_get().map(|value| *value)
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 2);
+        assert!(results
+            .iter()
+            .filter_map(|r| r.as_ref().ok())
+            .all(|&value| value == 2));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_atomic_ttl_drops_expired_values() {
+        let tracker = Arc::new(());
+        let cache = {
+            let tracker = Arc::clone(&tracker);
+            Arc::new(TryAtomicCache::with_ttl(
+                Box::new(move || Ok::<_, ()>(Arc::clone(&tracker))),
+                std::time::Duration::from_millis(5),
+            ))
+        };
+
+        for _ in 0..5 {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.try_get().is_ok())
+                .join()
+                .unwrap();
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(Arc::strong_count(&tracker), 3);
+    }
+
+    #[test]
+    fn test_atomic_poison_is_reported() {
+        let calls = AtomicUsize::new(0);
+        let cache = TryAtomicCache::new(|| {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first attempt fails");
+            }
+
+            Ok::<_, ()>(55)
+        })
+        .on_poison(PoisonPolicy::Error);
+
+        thread::scope(|s| {
+            assert!(s.spawn(|| cache.try_get().is_ok()).join().is_err());
+        });
+
+        assert_eq!(cache.try_get().err(), Some(TryGetError::Poisoned));
+
+        cache.invalidate();
+        assert_eq!(*cache.try_get().unwrap(), 55);
+    }
+}
@@ -0,0 +1,81 @@
+//! Ways of measuring how much of a keyed cache's capacity a value takes up.
+
+use std::mem;
+
+/// measures the weight of a value stored in a [`CacheMap`] or
+/// [`AtomicCacheMap`]. The capacity of a map is a budget for the total
+/// weight of its values.
+///
+/// By default every value weighs 1, so the capacity is a number of values.
+/// Any closure taking a key and a value can be used as a weigher as well.
+/// ```
+/// # use cache::CacheMap;
+/// let calc: Box<dyn Fn(&usize) -> String> = Box::new(|n| "x".repeat(*n));
+/// let strings = CacheMap::with_capacity(calc, 10)
+///     .weighed_by(|_: &usize, s: &String| s.len());
+///
+/// strings.get(&4);
+/// strings.get(&5);
+/// assert_eq!(strings.weight(), 9);
+///
+/// strings.get(&3);
+/// assert_eq!(strings.weight(), 8);
+/// ```
+///
+/// [`CacheMap`]: ./struct.CacheMap.html
+/// [`AtomicCacheMap`]: ./struct.AtomicCacheMap.html
+pub trait Weigher<K, V> {
+    /// the weight of `value`, which is about to be stored for `key`
+    fn weigh(&self, key: &K, value: &V) -> usize;
+}
+
+impl<K, V, F> Weigher<K, V> for F
+where
+    F: Fn(&K, &V) -> usize,
+{
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        self(key, value)
+    }
+}
+
+/// the default weigher, which gives every value a weight of 1
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnitWeigher;
+
+impl<K, V> Weigher<K, V> for UnitWeigher {
+    fn weigh(&self, _: &K, _: &V) -> usize {
+        1
+    }
+}
+
+/// weighs a [`Vec`] or [`String`] by the number of bytes it has allocated
+/// on the heap
+/// ```
+/// # use cache::{ByteWeigher, CacheMap};
+/// let calc: Box<dyn Fn(&usize) -> Vec<u32>> = Box::new(|n| vec![0; *n]);
+/// let buffers = CacheMap::with_capacity(calc, 1024).weighed_by(ByteWeigher);
+///
+/// buffers.get(&100);
+/// assert_eq!(buffers.weight(), 400);
+///
+/// // too big to ever fit, so it is handed out but not stored
+/// assert_eq!(buffers.get(&1000).len(), 1000);
+/// assert!(!buffers.contains_key(&1000));
+/// ```
+///
+/// [`Vec`]: https://doc.rust-lang.org/std/vec/struct.Vec.html
+/// [`String`]: https://doc.rust-lang.org/std/string/struct.String.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteWeigher;
+
+impl<K, T> Weigher<K, Vec<T>> for ByteWeigher {
+    fn weigh(&self, _: &K, value: &Vec<T>) -> usize {
+        value.capacity() * mem::size_of::<T>()
+    }
+}
+
+impl<K> Weigher<K, String> for ByteWeigher {
+    fn weigh(&self, _: &K, value: &String) -> usize {
+        value.capacity()
+    }
+}